@@ -7,7 +7,7 @@
 use num_enum::IntoPrimitive;
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "ext")]
-use skykit::msg::Message;
+use skykit::ipc::Peer;
 
 #[macro_use]
 extern crate bitfield_struct;
@@ -82,21 +82,16 @@ pub enum PCICfgOffset {
     MaximumLatency = 0x3F,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum PCIRequest {
-    Read8(PCIAddress, u8),
-    Read16(PCIAddress, u8),
-    Read32(PCIAddress, u8),
-    Write8(PCIAddress, u8, u8),
-    Write16(PCIAddress, u8, u16),
-    Write32(PCIAddress, u8, u32),
-}
-
+/// Configuration space access, served by the PCIKit process.
 #[cfg(feature = "ext")]
-impl PCIRequest {
-    pub unsafe fn send(self, pid: u64) {
-        Message::new(pid, postcard::to_allocvec(&self).unwrap().leak()).send();
-    }
+#[skykit::ipc::interface(name = "org.ChefKiss.PCIKit", version = 1)]
+pub trait PCIService {
+    fn read8(&mut self, addr: PCIAddress, off: u8) -> u8;
+    fn read16(&mut self, addr: PCIAddress, off: u8) -> u16;
+    fn read32(&mut self, addr: PCIAddress, off: u8) -> u32;
+    fn write8(&mut self, addr: PCIAddress, off: u8, value: u8);
+    fn write16(&mut self, addr: PCIAddress, off: u8, value: u16);
+    fn write32(&mut self, addr: PCIAddress, off: u8, value: u32);
 }
 
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
@@ -115,6 +110,11 @@ impl PCIDevice {
 
 #[cfg(feature = "ext")]
 impl PCIDevice {
+    #[inline]
+    const fn service(&self) -> PCIServiceClient {
+        PCIServiceClient::new(Peer::Pid(self.pid))
+    }
+
     #[must_use]
     pub unsafe fn is_multifunction(&self) -> bool {
         (self.cfg_read8::<_, u8>(PCICfgOffset::HeaderType) & 0x80) != 0
@@ -122,31 +122,34 @@ impl PCIDevice {
 
     #[must_use]
     pub unsafe fn cfg_read8<A: Into<u8>, R: From<u8>>(&self, off: A) -> R {
-        PCIRequest::Read8(self.addr, off.into()).send(self.pid);
-        Message::recv().data[0].into()
+        self.service().read8(self.addr, off.into()).unwrap().into()
     }
 
     #[must_use]
     pub unsafe fn cfg_read16<A: Into<u8>, R: From<u16>>(&self, off: A) -> R {
-        PCIRequest::Read16(self.addr, off.into()).send(self.pid);
-        u16::from_le_bytes(Message::recv().data.try_into().unwrap()).into()
+        self.service().read16(self.addr, off.into()).unwrap().into()
     }
 
     #[must_use]
     pub unsafe fn cfg_read32<A: Into<u8>, R: From<u32>>(&self, off: A) -> R {
-        PCIRequest::Read32(self.addr, off.into()).send(self.pid);
-        u32::from_le_bytes(Message::recv().data.try_into().unwrap()).into()
+        self.service().read32(self.addr, off.into()).unwrap().into()
     }
 
     pub unsafe fn cfg_write8<A: Into<u8>, R: Into<u8>>(&self, off: A, value: R) {
-        PCIRequest::Write8(self.addr, off.into(), value.into()).send(self.pid);
+        self.service()
+            .write8(self.addr, off.into(), value.into())
+            .unwrap();
     }
 
     pub unsafe fn cfg_write16<A: Into<u8>, R: Into<u16>>(&self, off: A, value: R) {
-        PCIRequest::Write16(self.addr, off.into(), value.into()).send(self.pid);
+        self.service()
+            .write16(self.addr, off.into(), value.into())
+            .unwrap();
     }
 
     pub unsafe fn cfg_write32<A: Into<u8>, R: Into<u32>>(&self, off: A, value: R) {
-        PCIRequest::Write32(self.addr, off.into(), value.into()).send(self.pid);
+        self.service()
+            .write32(self.addr, off.into(), value.into())
+            .unwrap();
     }
 }
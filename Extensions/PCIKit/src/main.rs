@@ -6,7 +6,6 @@
 
 // #[macro_use]
 // extern crate log;
-#[macro_use]
 extern crate alloc;
 #[macro_use]
 extern crate itertools;
@@ -14,7 +13,7 @@ extern crate itertools;
 use alloc::{boxed::Box, string::String};
 
 use hashbrown::HashMap;
-use pcikit::{PCIAddress, PCICfgOffset, PCIRequest};
+use pcikit::{PCIAddress, PCICfgOffset, PCIService, PCIServiceRequest};
 use skykit::{msg::Message, osdtentry::OSDTEntry, osvalue::OSValue, userspace::port::Port};
 
 trait PCIControllerIO: Sync {
@@ -28,32 +27,32 @@ trait PCIControllerIO: Sync {
 
 struct PCIController;
 
-impl PCIController {
-    fn read8(&self, addr: PCIAddress, off: u8) -> u8 {
+impl PCIService for PCIController {
+    fn read8(&mut self, addr: PCIAddress, off: u8) -> u8 {
         unsafe { PCIPortIO::new().read8(addr, off) }
     }
 
-    fn read16(&self, addr: PCIAddress, off: u8) -> u16 {
+    fn read16(&mut self, addr: PCIAddress, off: u8) -> u16 {
         unsafe { PCIPortIO::new().read16(addr, off) }
     }
 
-    fn read32(&self, addr: PCIAddress, off: u8) -> u32 {
+    fn read32(&mut self, addr: PCIAddress, off: u8) -> u32 {
         unsafe { PCIPortIO::new().read32(addr, off) }
     }
 
-    fn write8(&self, addr: PCIAddress, off: u8, value: u8) {
+    fn write8(&mut self, addr: PCIAddress, off: u8, value: u8) {
         unsafe {
             PCIPortIO::new().write8(addr, off, value);
         }
     }
 
-    fn write16(&self, addr: PCIAddress, off: u8, value: u16) {
+    fn write16(&mut self, addr: PCIAddress, off: u8, value: u16) {
         unsafe {
             PCIPortIO::new().write16(addr, off, value);
         }
     }
 
-    fn write32(&self, addr: PCIAddress, off: u8, value: u32) {
+    fn write32(&mut self, addr: PCIAddress, off: u8, value: u32) {
         unsafe {
             PCIPortIO::new().write32(addr, off, value);
         }
@@ -119,7 +118,7 @@ impl PCIControllerIO for PCIPortIO {
 extern "C" fn _start(instance: OSDTEntry) -> ! {
     skykit::userspace::logger::init();
 
-    let controller = Box::new(PCIController);
+    let mut controller = Box::new(PCIController);
     for (bus, slot) in iproduct!(0..=255, 0..32) {
         for func in 0..8 {
             let addr = PCIAddress::new(0, bus, slot, func);
@@ -155,34 +154,17 @@ extern "C" fn _start(instance: OSDTEntry) -> ! {
         }
     }
 
+    assert!(
+        skykit::service::register("org.ChefKiss.PCIKit"),
+        "PCIKit is already running"
+    );
+
     loop {
-        let msg = unsafe { Message::recv() };
+        let mut msg = unsafe { Message::recv() };
         if msg.pid == 0 {
             continue;
         }
 
-        let Ok(req) = postcard::from_bytes::<PCIRequest>(msg.data) else {
-            continue;
-        };
-        let data = match req {
-            PCIRequest::Read8(addr, off) => vec![controller.read8(addr, off)],
-            PCIRequest::Read16(addr, off) => controller.read16(addr, off).to_le_bytes().to_vec(),
-            PCIRequest::Read32(addr, off) => controller.read32(addr, off).to_le_bytes().to_vec(),
-            PCIRequest::Write8(addr, off, value) => {
-                controller.write8(addr, off, value);
-                continue;
-            }
-            PCIRequest::Write16(addr, off, value) => {
-                controller.write16(addr, off, value);
-                continue;
-            }
-            PCIRequest::Write32(addr, off, value) => {
-                controller.write32(addr, off, value);
-                continue;
-            }
-        };
-        unsafe {
-            Message::new(msg.pid, data.leak()).send();
-        }
+        PCIServiceRequest::handle(&mut *controller, &mut msg);
     }
 }
@@ -17,11 +17,13 @@ use core::fmt::Write;
 use num_enum::IntoPrimitive;
 use serde::{Deserialize, Serialize};
 use skykit::{
-    msg::Message,
-    osdtentry::{OSDTEntry, OSDTENTRY_NAME_KEY, SKEXT_PROC_KEY},
+    channel::Channel,
+    msg::{Message, QueueFullPolicy, QueueStats, RecvFilter, SendFlags, SendResult},
+    osdtentry::{OSDTEntry, OSDTENTRY_NAME_KEY},
     osvalue::OSValue,
+    service,
     syscall::SystemCall,
-    userspace::{logger::KWriter, port::Port},
+    userspace::{logger::KWriter, port::Port, thread},
 };
 
 #[derive(IntoPrimitive)]
@@ -138,19 +140,14 @@ fn print_ent(ent: OSDTEntry, ident: usize) {
     }
 }
 
-#[no_mangle]
-extern "C" fn _start(instance: OSDTEntry) -> ! {
-    skykit::userspace::logger::init();
+const KEYBOARD_CHANNEL: &str = "org.ChefKiss.SKTest.Keyboard";
 
-    let this = PS2Ctl::new();
-    this.init();
-    let mut s = String::new();
-    write!(KWriter, "> ").unwrap();
+/// Decodes the scancodes of every keyboard IRQ and publishes them on
+/// [`KEYBOARD_CHANNEL`].
+fn keyboard_main(this: &PS2Ctl, channel: &Channel<Ps2Event>) -> ! {
     loop {
-        let msg = unsafe { Message::recv() };
-        if msg.pid != 0 {
-            continue;
-        }
+        // Acknowledged at the end of the iteration, which unmasks the IRQ
+        let _msg = unsafe { Message::recv_filtered(RecvFilter::Kernel) };
 
         while this.output_full() {
             let event = match unsafe { this.data_port.read() } {
@@ -172,61 +169,81 @@ extern "C" fn _start(instance: OSDTEntry) -> ! {
                 0x39 => Ps2Event::Pressed(' '),
                 v => Ps2Event::Other(v),
             };
+            channel.publish(&event);
+        }
+    }
+}
 
-            let Ps2Event::Pressed(ch) = event else {
-                continue;
-            };
-            write!(KWriter, "{ch}").unwrap();
+#[no_mangle]
+extern "C" fn _start(_instance: OSDTEntry) -> ! {
+    skykit::userspace::logger::init();
 
-            if ch != '\n' {
-                s.push(ch);
-                continue;
-            }
+    let channel = Channel::create(KEYBOARD_CHANNEL).unwrap();
+    let keys = Channel::<Ps2Event>::subscribe(KEYBOARD_CHANNEL).unwrap();
+    thread::spawn(move || {
+        let this = PS2Ctl::new();
+        this.init();
+        keyboard_main(&this, &channel)
+    });
 
-            match s.as_str() {
-                "osdt" => print_ent(OSDTEntry::default(), 0),
-                "msgparent" => {
-                    let pid: u64 = instance
-                        .parent()
-                        .unwrap()
-                        .parent()
-                        .unwrap()
-                        .get_property(SKEXT_PROC_KEY)
-                        .unwrap()
-                        .try_into()
-                        .unwrap();
-
-                    unsafe {
-                        Message::new(pid, vec![1, 2, 3, 4].leak()).send();
-                    }
+    let mut s = String::new();
+    write!(KWriter, "> ").unwrap();
+    loop {
+        let Ps2Event::Pressed(ch) = keys.recv() else {
+            continue;
+        };
+        write!(KWriter, "{ch}").unwrap();
+
+        if ch != '\n' {
+            s.push(ch);
+            continue;
+        }
+
+        match s.as_str() {
+            "osdt" => print_ent(OSDTEntry::default(), 0),
+            "queue" => writeln!(KWriter, "{:?}", unsafe { QueueStats::get() }).unwrap(),
+            "msgparent" => 'a: {
+                let Some(pid) = service::lookup("org.ChefKiss.PCIKit") else {
+                    writeln!(KWriter, "PCIKit is not running").unwrap();
+                    break 'a;
+                };
+
+                unsafe {
+                    Message::new(pid, vec![1, 2, 3, 4].leak()).send();
                 }
-                "accessinvalid" => unsafe {
-                    core::arch::asm!(
-                        "int 249",
-                        in("rdi") SystemCall::KPrint as u64,
-                        in("rsi") 0u64,
-                        in("rdx") 0u64,
-                        options(nostack),
-                    );
-                },
-                v if v.split_whitespace().next() == Some("msg") => 'a: {
-                    let mut v = v.split_whitespace().skip(1);
-                    let Some(pid) = v.next().and_then(|v| v.parse().ok()) else {
-                        writeln!(KWriter, "Expected PID").unwrap();
-                        break 'a;
-                    };
-                    let Some(data) = v.next().and_then(|v| v.parse::<u64>().ok()) else {
-                        writeln!(KWriter, "Expected data").unwrap();
-                        break 'a;
-                    };
-                    unsafe {
-                        Message::new(pid, data.to_be_bytes().to_vec().leak()).send();
-                    }
+            }
+            "accessinvalid" => unsafe {
+                core::arch::asm!(
+                    "int 249",
+                    in("rdi") SystemCall::KPrint as u64,
+                    in("rsi") 0u64,
+                    in("rdx") 0u64,
+                    options(nostack),
+                );
+            },
+            v if v.split_whitespace().next() == Some("msg") => 'a: {
+                let mut v = v.split_whitespace().skip(1);
+                let Some(pid) = v.next().and_then(|v| v.parse().ok()) else {
+                    writeln!(KWriter, "Expected PID").unwrap();
+                    break 'a;
+                };
+                let Some(data) = v.next().and_then(|v| v.parse::<u64>().ok()) else {
+                    writeln!(KWriter, "Expected data").unwrap();
+                    break 'a;
+                };
+                // Don't let a stuck receiver hang the shell
+                let flags = SendFlags {
+                    on_full: QueueFullPolicy::Fail,
+                    ..Default::default()
+                };
+                let msg = Message::new(pid, data.to_be_bytes().to_vec().leak());
+                if unsafe { msg.send_with(flags) } == SendResult::QueueFull {
+                    writeln!(KWriter, "Queue of PID {pid} is full").unwrap();
                 }
-                _ => writeln!(KWriter, "{s}").unwrap(),
             }
-            write!(KWriter, "> ").unwrap();
-            s.clear();
+            _ => writeln!(KWriter, "{s}").unwrap(),
         }
+        write!(KWriter, "> ").unwrap();
+        s.clear();
     }
 }
@@ -31,7 +31,7 @@ pub struct CPUFeatures {
     __: bool,
     pub movbe: bool,
     pub popcnt: bool,
-    __: bool,
+    pub tsc_deadline: bool,
     pub aes: bool,
     pub xsave: bool,
     pub osxsave: bool,
@@ -77,6 +77,10 @@ pub struct CPUIdentification {
     pub vendor_string: ArrayString<12>,
     pub features: CPUFeatures,
     pub misc: FeaturesMisc,
+    /// The TSC ticks at a constant rate in all P-, C- and T-states.
+    pub invariant_tsc: bool,
+    /// The LAPIC timer keeps running in deep C-states.
+    pub always_running_apic_timer: bool,
 }
 
 impl Default for CPUIdentification {
@@ -103,11 +107,22 @@ impl CPUIdentification {
         let features = CPUFeatures::from(u64::from(res.ecx) | (u64::from(res.edx) << 32));
         let misc = FeaturesMisc::from(res.ebx);
 
+        // Function 0x8000_0007, Advanced Power Management
+        let largest_ext_func_id = unsafe { core::arch::x86_64::__cpuid(0x8000_0000) }.eax;
+        let invariant_tsc = largest_ext_func_id >= 0x8000_0007
+            && unsafe { core::arch::x86_64::__cpuid(0x8000_0007) }.edx & (1 << 8) != 0;
+
+        // Function 6, Thermal and Power Management
+        let always_running_apic_timer =
+            largest_func_id >= 6 && unsafe { core::arch::x86_64::__cpuid(6) }.eax & (1 << 2) != 0;
+
         Self {
             largest_func_id,
             vendor_string,
             features,
             misc,
+            invariant_tsc,
+            always_running_apic_timer,
         }
     }
 }
@@ -3,6 +3,9 @@
 pub mod apic;
 pub mod efer;
 pub mod pat;
+pub mod seg_base;
+pub mod syscall;
+pub mod tsc_deadline;
 pub mod vm_cr;
 
 pub trait ModelSpecificReg: Sized + From<u64> {
@@ -0,0 +1,29 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+#[bitfield(u64)]
+pub struct FsBase {
+    pub base: u64,
+}
+
+impl super::ModelSpecificReg for FsBase {
+    const MSR_NUM: u32 = 0xC000_0100;
+}
+
+#[bitfield(u64)]
+pub struct GsBase {
+    pub base: u64,
+}
+
+impl super::ModelSpecificReg for GsBase {
+    const MSR_NUM: u32 = 0xC000_0101;
+}
+
+/// Swapped with [`GsBase`] by `swapgs`.
+#[bitfield(u64)]
+pub struct KernelGsBase {
+    pub base: u64,
+}
+
+impl super::ModelSpecificReg for KernelGsBase {
+    const MSR_NUM: u32 = 0xC000_0102;
+}
@@ -0,0 +1,36 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+/// `syscall` loads CS from `syscall_cs` and SS from `syscall_cs + 8`.
+/// `sysret` to 64-bit mode loads CS from `sysret_cs + 16` and SS from
+/// `sysret_cs + 8`, both with RPL 3.
+#[bitfield(u64)]
+pub struct Star {
+    pub legacy_eip: u32,
+    pub syscall_cs: u16,
+    pub sysret_cs: u16,
+}
+
+impl super::ModelSpecificReg for Star {
+    const MSR_NUM: u32 = 0xC000_0081;
+}
+
+/// Long mode `syscall` entry point.
+#[bitfield(u64)]
+pub struct LStar {
+    pub rip: u64,
+}
+
+impl super::ModelSpecificReg for LStar {
+    const MSR_NUM: u32 = 0xC000_0082;
+}
+
+/// RFLAGS bits cleared on `syscall`.
+#[bitfield(u64)]
+pub struct SfMask {
+    pub mask: u32,
+    __: u32,
+}
+
+impl super::ModelSpecificReg for SfMask {
+    const MSR_NUM: u32 = 0xC000_0084;
+}
@@ -0,0 +1,134 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+//! Named broadcast channels with one publisher and any number of
+//! subscribers. Every subscription queues up to [`CHANNEL_DEPTH`] payloads,
+//! a subscriber that falls behind loses the oldest ones.
+
+#[cfg(feature = "userspace")]
+use core::marker::PhantomData;
+
+#[cfg(feature = "userspace")]
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "userspace")]
+use crate::{
+    msg::{Endpoint, Message, RecvFilter},
+    syscall::SystemCall,
+};
+
+pub const CHANNEL_DEPTH: usize = 16;
+pub const MAX_CHANNEL_PAYLOAD: u64 = 0x10000;
+
+#[cfg(feature = "userspace")]
+fn name_syscall(call: SystemCall, name: &str) -> u64 {
+    let ret: u64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") call as u64,
+            in("rsi") name.as_ptr() as u64,
+            in("rdx") name.len() as u64,
+            lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    ret
+}
+
+/// The publishing side of a channel carrying `T`s.
+#[cfg(feature = "userspace")]
+pub struct Channel<T> {
+    id: u64,
+    _marker: PhantomData<fn(T)>,
+}
+
+#[cfg(feature = "userspace")]
+impl<T: Serialize> Channel<T> {
+    /// Creates the channel `name` with this process as its publisher, if
+    /// nobody took the name yet.
+    #[must_use]
+    pub fn create(name: &str) -> Option<Self> {
+        match name_syscall(SystemCall::CreateChannel, name) {
+            0 => None,
+            id => Some(Self {
+                id,
+                _marker: PhantomData,
+            }),
+        }
+    }
+
+    /// Hands every subscriber a copy of `value`.
+    pub fn publish(&self, value: &T) {
+        let data = postcard::to_allocvec(value).unwrap();
+        unsafe {
+            core::arch::asm!(
+                "syscall",
+                in("rdi") SystemCall::PublishChannel as u64,
+                in("rsi") self.id,
+                in("rdx") data.as_ptr() as u64,
+                in("r10") data.len() as u64,
+                out("rcx") _,
+                out("r11") _,
+                options(nostack),
+            );
+        }
+    }
+}
+
+#[cfg(feature = "userspace")]
+impl<T: DeserializeOwned> Channel<T> {
+    /// Starts receiving what gets published on `name` from now on, if it
+    /// exists.
+    #[must_use]
+    pub fn subscribe(name: &str) -> Option<Subscription<T>> {
+        match name_syscall(SystemCall::SubscribeChannel, name) {
+            0 => None,
+            id => Some(Subscription {
+                endpoint: Endpoint(id),
+                _marker: PhantomData,
+            }),
+        }
+    }
+}
+
+/// The payloads arrive as messages on [`Self::endpoint`], which can be
+/// waited on together with other endpoints.
+#[cfg(feature = "userspace")]
+pub struct Subscription<T> {
+    endpoint: Endpoint,
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "userspace")]
+impl<T: DeserializeOwned> Subscription<T> {
+    #[must_use]
+    pub const fn endpoint(&self) -> Endpoint {
+        self.endpoint
+    }
+
+    /// Decodes a message received on [`Self::endpoint`].
+    #[must_use]
+    pub fn decode(msg: &Message) -> T {
+        postcard::from_bytes(msg.data).unwrap()
+    }
+
+    /// Waits for the oldest payload still queued.
+    #[must_use]
+    pub fn recv(&self) -> T {
+        Self::decode(&unsafe { Message::recv_on(&[self.endpoint], RecvFilter::Any) })
+    }
+
+    #[must_use]
+    pub fn try_recv(&self) -> Option<T> {
+        unsafe { Message::try_recv_on(&[self.endpoint], RecvFilter::Any) }.map(|v| Self::decode(&v))
+    }
+}
+
+#[cfg(feature = "userspace")]
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        unsafe { self.endpoint.destroy() }
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+//! Typed request/response IPC over [`Message::call`]. Protocols are
+//! declared as traits with [`interface`], this is what the generated code
+//! runs on.
+//!
+//! A request is the postcard encoding of the [`InterfaceId`] followed by
+//! the request enum, the answer that of a `Result<T, IpcError>`.
+
+#[cfg(feature = "userspace")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "userspace")]
+pub use skykit_macros::interface;
+
+use crate::msg::Endpoint;
+#[cfg(feature = "userspace")]
+use crate::msg::Message;
+
+/// Identifies an interface and its revision on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterfaceId {
+    /// FNV-1a hash of the interface name.
+    pub name_hash: u64,
+    pub version: u32,
+}
+
+impl InterfaceId {
+    #[must_use]
+    pub const fn new(name: &str, version: u32) -> Self {
+        let name = name.as_bytes();
+        let mut name_hash = 0xCBF2_9CE4_8422_2325u64;
+        let mut i = 0;
+        while i < name.len() {
+            name_hash ^= name[i] as u64;
+            name_hash = name_hash.wrapping_mul(0x100_0000_01B3);
+            i += 1;
+        }
+        Self { name_hash, version }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IpcError {
+    /// The peer implements another interface, or another version of it.
+    InterfaceMismatch(InterfaceId),
+    /// The request or response could not be decoded.
+    Malformed,
+    /// The peer exited without answering.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Pid(u64),
+    Endpoint(Endpoint),
+}
+
+/// Sends `req` for `interface` to `peer` and waits for the result.
+#[cfg(feature = "userspace")]
+pub fn call<Req: Serialize, Resp: DeserializeOwned>(
+    peer: Peer,
+    interface: InterfaceId,
+    req: &Req,
+) -> Result<Resp, IpcError> {
+    let data = postcard::to_allocvec(&(interface, req)).unwrap().leak();
+    let reply = unsafe {
+        match peer {
+            Peer::Pid(pid) => Message::call(pid, data),
+            Peer::Endpoint(endpoint) => Message::call_endpoint(endpoint, data),
+        }
+    };
+    if reply.data.is_empty() {
+        return Err(IpcError::Disconnected);
+    }
+    postcard::from_bytes::<Result<Resp, IpcError>>(reply.data).map_err(|_| IpcError::Malformed)?
+}
+
+/// Decodes the request for `interface` in `msg`. If that fails, the
+/// sender gets told why instead.
+#[cfg(feature = "userspace")]
+pub fn decode_request<Req: DeserializeOwned>(
+    interface: InterfaceId,
+    msg: &mut Message,
+) -> Option<Req> {
+    let ret = postcard::take_from_bytes::<InterfaceId>(msg.data)
+        .map_err(|_| IpcError::Malformed)
+        .and_then(|(id, rest)| {
+            if id != interface {
+                return Err(IpcError::InterfaceMismatch(interface));
+            }
+            postcard::from_bytes(rest).map_err(|_| IpcError::Malformed)
+        });
+    match ret {
+        Ok(v) => Some(v),
+        Err(e) => {
+            reply::<()>(msg, Err(e));
+            None
+        }
+    }
+}
+
+/// Answers `msg` with `result`, if the sender waits for an answer.
+#[cfg(feature = "userspace")]
+pub fn reply<T: Serialize>(msg: &mut Message, result: Result<T, IpcError>) {
+    if msg.reply_token.is_none() {
+        return;
+    }
+    let data = postcard::to_allocvec(&result).unwrap().leak();
+    unsafe { msg.reply(data) }
+}
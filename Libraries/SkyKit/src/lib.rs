@@ -14,13 +14,24 @@ extern crate alloc;
 #[macro_use]
 extern crate log;
 
+pub mod channel;
+pub mod ipc;
 pub mod msg;
+pub mod notification;
 pub mod osdtentry;
 pub mod osvalue;
+#[cfg(feature = "userspace")]
+pub mod service;
+pub mod shared_mem;
+#[cfg(feature = "userspace")]
+pub mod sync;
 pub mod syscall;
 #[cfg(feature = "userspace")]
+pub mod time;
+#[cfg(feature = "userspace")]
 pub mod userspace;
 
+pub use serde;
 use serde::{Deserialize, Serialize};
 
 pub const USER_VIRT_OFFSET: u64 = 0xC000_0000;
@@ -50,3 +61,14 @@ pub enum TerminationReason {
     AlreadyExists,
     InsufficientPermissions,
 }
+
+/// How a process ended, see [`msg::KernelMessage::ProcessExited`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ExitReason {
+    /// It quit, or its last thread exited, with this code.
+    Quit(u64),
+    /// The kernel killed it over a system call.
+    Killed(TerminationReason),
+    /// It raised the CPU exception with this vector.
+    Exception(u8),
+}
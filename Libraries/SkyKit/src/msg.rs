@@ -1,15 +1,198 @@
 // Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
 
+#[cfg(feature = "userspace")]
+use core::time::Duration;
+
+use num_enum::TryFromPrimitive;
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "userspace")]
 use super::syscall::SystemCall;
+use super::ExitReason;
+
+/// Which messages a receive takes, the rest stay queued.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RecvFilter {
+    #[default]
+    Any,
+    /// Only [`KernelMessage`]s.
+    Kernel,
+    Pid(u64),
+}
+
+impl RecvFilter {
+    #[must_use]
+    pub const fn into_raw(self) -> u64 {
+        match self {
+            Self::Any => u64::MAX,
+            Self::Kernel => 0,
+            Self::Pid(pid) => pid,
+        }
+    }
+
+    #[must_use]
+    pub const fn from_raw(value: u64) -> Self {
+        match value {
+            u64::MAX => Self::Any,
+            0 => Self::Kernel,
+            pid => Self::Pid(pid),
+        }
+    }
+
+    #[must_use]
+    pub const fn matches(self, pid: u64) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Kernel => pid == 0,
+            Self::Pid(v) => v == pid,
+        }
+    }
+}
+
+/// Messages a process can have queued until it changes that.
+pub const DEFAULT_QUEUE_LIMIT: u64 = 64;
+pub const MAX_QUEUE_LIMIT: u64 = 4096;
+
+/// What a send does when the receiver's queue is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+#[repr(u64)]
+pub enum QueueFullPolicy {
+    /// Wait until the receiver takes a message.
+    #[default]
+    Block,
+    /// Give up with [`SendResult::QueueFull`].
+    Fail,
+    /// Discard the message, which counts towards [`QueueStats::dropped`].
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+#[repr(u64)]
+pub enum SendResult {
+    Sent,
+    QueueFull,
+    Dropped,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SendFlags {
+    /// Hand the pages to the receiver, see [`Message::send_move`].
+    pub move_pages: bool,
+    pub on_full: QueueFullPolicy,
+}
+
+impl SendFlags {
+    #[must_use]
+    pub const fn into_raw(self) -> u64 {
+        self.move_pages as u64 | (self.on_full as u64) << 1
+    }
+
+    #[must_use]
+    pub fn from_raw(value: u64) -> Option<Self> {
+        Some(Self {
+            move_pages: value & 1 != 0,
+            on_full: QueueFullPolicy::try_from(value >> 1).ok()?,
+        })
+    }
+}
+
+/// Diagnostics of the message queue of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub len: u64,
+    /// Most messages that were ever queued at once.
+    pub high_water: u64,
+    /// Depth at which sends to the process stop going through.
+    pub limit: u64,
+    pub dropped: u64,
+}
+
+#[cfg(feature = "userspace")]
+impl QueueStats {
+    #[must_use]
+    pub unsafe fn get() -> Self {
+        let (len, high_water, limit, dropped): (u64, u64, u64, u64);
+        core::arch::asm!(
+            "syscall",
+            inlateout("rdi") SystemCall::GetQueueStats as u64 => high_water,
+            out("rax") len,
+            out("rsi") limit,
+            out("rdx") dropped,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        Self {
+            len,
+            high_water,
+            limit,
+            dropped,
+        }
+    }
+
+    /// Sets how many messages this process' queue holds, at most
+    /// [`MAX_QUEUE_LIMIT`].
+    pub unsafe fn set_limit(limit: u64) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::SetQueueLimit as u64,
+            in("rsi") limit,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+}
+
+/// A receive endpoint of a process. Handles are plain IDs that can be passed
+/// around in messages, anyone knowing one can send to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(transparent)]
+pub struct Endpoint(pub u64);
+
+impl Endpoint {
+    /// Where messages addressed to a bare PID land, every process has one.
+    pub const DEFAULT: Self = Self(0);
+}
+
+#[cfg(feature = "userspace")]
+impl Endpoint {
+    #[must_use]
+    pub unsafe fn create() -> Self {
+        let id: u64;
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::CreateEndpoint as u64,
+            out("rax") id,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        Self(id)
+    }
+
+    /// Messages already queued on the endpoint stay queued.
+    pub unsafe fn destroy(self) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::DestroyEndpoint as u64,
+            in("rsi") self.0,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Message {
     pub id: u64,
     pub pid: u64,
     pub data: &'static [u8],
+    /// Set if the sender is blocked in [`Message::call`] waiting for an answer.
+    pub reply_token: Option<u64>,
+    /// The endpoint of the receiving process the message is for.
+    pub endpoint: Endpoint,
 }
 
 impl Message {
@@ -17,48 +200,236 @@ impl Message {
     #[inline]
     #[must_use]
     pub const fn new(id: u64, pid: u64, data: &'static [u8]) -> Self {
-        Self { id, pid, data }
+        Self {
+            id,
+            pid,
+            data,
+            reply_token: None,
+            endpoint: Endpoint::DEFAULT,
+        }
     }
 
     #[cfg(feature = "userspace")]
     #[inline]
     #[must_use]
     pub const fn new(pid: u64, data: &'static [u8]) -> Self {
-        Self { id: 0, pid, data }
+        Self {
+            id: 0,
+            pid,
+            data,
+            reply_token: None,
+            endpoint: Endpoint::DEFAULT,
+        }
+    }
+
+    #[cfg(feature = "userspace")]
+    #[inline]
+    #[must_use]
+    pub const fn new_to(endpoint: Endpoint, data: &'static [u8]) -> Self {
+        Self {
+            id: 0,
+            pid: 0,
+            data,
+            reply_token: None,
+            endpoint,
+        }
     }
 }
 
 #[cfg(feature = "userspace")]
 impl Message {
+    /// Waits for the oldest message.
     #[must_use]
     pub unsafe fn recv() -> Self {
+        Self::recv_filtered(RecvFilter::Any)
+    }
+
+    /// Waits for the oldest message matching `filter`.
+    #[must_use]
+    pub unsafe fn recv_filtered(filter: RecvFilter) -> Self {
+        Self::recv_raw(filter, &[], 0, false).unwrap()
+    }
+
+    /// Takes the oldest message matching `filter` without blocking.
+    #[must_use]
+    pub unsafe fn try_recv(filter: RecvFilter) -> Option<Self> {
+        Self::recv_raw(filter, &[], 0, true)
+    }
+
+    /// Waits for the oldest message matching `filter` on any of `endpoints`.
+    /// An empty set means all endpoints of the process.
+    #[must_use]
+    pub unsafe fn recv_on(endpoints: &[Endpoint], filter: RecvFilter) -> Self {
+        Self::recv_raw(filter, endpoints, 0, false).unwrap()
+    }
+
+    /// Like [`Self::recv_filtered`], but gives up after `timeout`, with
+    /// millisecond granularity.
+    #[must_use]
+    pub unsafe fn recv_timeout(filter: RecvFilter, timeout: Duration) -> Option<Self> {
+        Self::recv_on_timeout(&[], filter, timeout)
+    }
+
+    /// Like [`Self::recv_on`], but without blocking.
+    #[must_use]
+    pub unsafe fn try_recv_on(endpoints: &[Endpoint], filter: RecvFilter) -> Option<Self> {
+        Self::recv_raw(filter, endpoints, 0, true)
+    }
+
+    /// Like [`Self::recv_on`], but gives up after `timeout`.
+    #[must_use]
+    pub unsafe fn recv_on_timeout(
+        endpoints: &[Endpoint],
+        filter: RecvFilter,
+        timeout: Duration,
+    ) -> Option<Self> {
+        let ms = timeout
+            .as_nanos()
+            .div_ceil(1_000_000)
+            .clamp(1, u64::MAX.into()) as u64;
+        Self::recv_raw(filter, endpoints, ms, false)
+    }
+
+    unsafe fn recv_raw(
+        filter: RecvFilter,
+        endpoints: &[Endpoint],
+        timeout_ms: u64,
+        non_blocking: bool,
+    ) -> Option<Self> {
         let (mut id, mut pid): (u64, u64);
         let (mut ptr, mut len): (u64, u64);
+        let (reply_token, endpoint): (u64, u64);
         core::arch::asm!(
-            "int 249",
-            in("rdi") SystemCall::MsgRecv as u64,
+            "syscall",
+            inlateout("rdi") SystemCall::MsgRecv as u64 => pid,
+            inlateout("rsi") filter.into_raw() => ptr,
+            inlateout("rdx") timeout_ms => len,
+            in("r10") u64::from(non_blocking),
+            inlateout("r8") endpoints.as_ptr() as u64 => reply_token,
+            inlateout("r9") endpoints.len() as u64 => endpoint,
             out("rax") id,
-            lateout("rdi") pid,
-            out("rsi") ptr,
-            out("rdx") len,
+            out("rcx") _,
+            out("r11") _,
             options(nostack),
         );
+        (id != 0).then(|| Self::from_raw(id, pid, ptr, len, reply_token, endpoint))
+    }
+
+    unsafe fn from_raw(
+        id: u64,
+        pid: u64,
+        ptr: u64,
+        len: u64,
+        reply_token: u64,
+        endpoint: u64,
+    ) -> Self {
         Self {
             id,
             pid,
-            data: core::slice::from_raw_parts(ptr as *const u8, len as _),
+            data: if len == 0 {
+                &[]
+            } else {
+                core::slice::from_raw_parts(ptr as *const u8, len as _)
+            },
+            reply_token: (reply_token != 0).then_some(reply_token),
+            endpoint: Endpoint(endpoint),
         }
     }
 
+    /// Sends `data` to `pid` and blocks until it answers with [`Self::reply`],
+    /// waiting for space first if its queue is full. The answer is empty if
+    /// `pid` exits without replying.
+    #[must_use]
+    pub unsafe fn call(pid: u64, data: &'static [u8]) -> Self {
+        Self::call_raw(pid, Endpoint::DEFAULT, data)
+    }
+
+    /// Like [`Self::call`], but to whichever process owns `endpoint`.
+    #[must_use]
+    pub unsafe fn call_endpoint(endpoint: Endpoint, data: &'static [u8]) -> Self {
+        Self::call_raw(0, endpoint, data)
+    }
+
+    unsafe fn call_raw(pid: u64, endpoint: Endpoint, data: &'static [u8]) -> Self {
+        let (mut id, mut reply_pid): (u64, u64);
+        let (mut ptr, mut len): (u64, u64);
+        let reply_token: u64;
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::MsgCall as u64,
+            in("rsi") pid,
+            in("rdx") data.as_ptr() as u64,
+            in("r10") data.len() as u64,
+            inlateout("r8") endpoint.0 => reply_token,
+            out("rax") id,
+            lateout("rdi") reply_pid,
+            lateout("rsi") ptr,
+            lateout("rdx") len,
+            out("r9") _,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        Self::from_raw(id, reply_pid, ptr, len, reply_token, 0)
+    }
+
+    /// Answers a message received from [`Self::call`]. Every call can only
+    /// be answered once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sender doesn't expect a reply, or got one already.
+    pub unsafe fn reply(&mut self, data: &'static [u8]) {
+        let token = self
+            .reply_token
+            .take()
+            .expect("Message does not expect a reply");
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::MsgReply as u64,
+            in("rsi") token,
+            in("rdx") data.as_ptr() as u64,
+            in("r10") data.len() as u64,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    /// Sends to [`Self::endpoint`] if it isn't the default one, otherwise to
+    /// [`Self::pid`]. Waits for space if the receiver's queue is full. A
+    /// buffer can only be in one message until that is acknowledged.
     pub unsafe fn send(self) {
+        let _ = self.send_with(SendFlags::default());
+    }
+
+    /// Like [`Self::send`], but hands the pages of [`Self::data`] to the
+    /// receiver instead of sharing them until it is done. The data must be a
+    /// whole allocation, like a leaked `Vec`, and is gone from this process
+    /// afterwards.
+    pub unsafe fn send_move(self) {
+        let _ = self.send_with(SendFlags {
+            move_pages: true,
+            ..Default::default()
+        });
+    }
+
+    pub unsafe fn send_with(self, flags: SendFlags) -> SendResult {
+        let ret: u64;
         core::arch::asm!(
-            "int 249",
+            "syscall",
             in("rdi") SystemCall::MsgSend as u64,
             in("rsi") self.pid,
             in("rdx") self.data.as_ptr() as u64,
-            in("rcx") self.data.len() as u64,
+            in("r10") self.data.len() as u64,
+            in("r8") self.endpoint.0,
+            in("r9") flags.into_raw(),
+            lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
             options(nostack),
         );
+        SendResult::try_from(ret).unwrap()
     }
 }
 
@@ -70,9 +441,11 @@ impl Drop for Message {
         }
         unsafe {
             core::arch::asm!(
-                "int 249",
+                "syscall",
                 in("rdi") SystemCall::MsgAck as u64,
                 in("rsi") self.id,
+                out("rcx") _,
+                out("r11") _,
                 options(nostack),
             );
         }
@@ -83,4 +456,10 @@ impl Drop for Message {
 #[repr(C)]
 pub enum KernelMessage {
     IRQFired(u8),
+    /// Sent to the extension `pid` was loaded for, and to every process
+    /// monitoring it.
+    ProcessExited {
+        pid: u64,
+        reason: ExitReason,
+    },
 }
@@ -0,0 +1,112 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+//! Notifications are words of event bits that other processes and bound
+//! IRQs set, and the owner takes all at once. A much lighter alternative to
+//! a message when all there is to say is "this happened".
+
+#[cfg(feature = "userspace")]
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "userspace")]
+use crate::syscall::SystemCall;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(transparent)]
+pub struct Notification(pub u64);
+
+#[cfg(feature = "userspace")]
+impl Notification {
+    #[must_use]
+    pub unsafe fn create() -> Self {
+        let id: u64;
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::CreateNotification as u64,
+            out("rax") id,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        Self(id)
+    }
+
+    /// IRQs bound to it stay masked.
+    pub unsafe fn destroy(self) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::DestroyNotification as u64,
+            in("rsi") self.0,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    pub unsafe fn signal(self, bits: u64) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::Signal as u64,
+            in("rsi") self.0,
+            in("rdx") bits,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    /// Makes IRQ `irq` set `bit`. The IRQ stays masked from when it fires
+    /// until the bit is taken, and the other handlers of the line are done.
+    pub unsafe fn bind_irq(self, irq: u8, bit: u8) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::BindIRQ as u64,
+            in("rsi") u64::from(irq),
+            in("rdx") self.0,
+            in("r10") u64::from(bit),
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    /// Waits until any bit is set, then takes them all.
+    #[must_use]
+    pub unsafe fn wait(self) -> u64 {
+        self.wait_raw(0, false)
+    }
+
+    /// Takes the bits set so far without blocking, possibly none.
+    #[must_use]
+    pub unsafe fn poll(self) -> u64 {
+        self.wait_raw(0, true)
+    }
+
+    /// Like [`Self::wait`], but returns no bits after `timeout`, with
+    /// millisecond granularity.
+    #[must_use]
+    pub unsafe fn wait_timeout(self, timeout: Duration) -> u64 {
+        let ms = timeout
+            .as_nanos()
+            .div_ceil(1_000_000)
+            .clamp(1, u64::MAX.into()) as u64;
+        self.wait_raw(ms, false)
+    }
+
+    unsafe fn wait_raw(self, timeout_ms: u64, non_blocking: bool) -> u64 {
+        let bits: u64;
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::WaitNotification as u64,
+            in("rsi") self.0,
+            in("rdx") timeout_ms,
+            in("r10") u64::from(non_blocking),
+            lateout("rax") bits,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        bits
+    }
+}
@@ -40,14 +40,16 @@ impl OSDTEntry {
         let (mut ptr, mut len): (u64, u64);
         unsafe {
             core::arch::asm!(
-                "int 249",
+                "syscall",
                 in("rdi") SystemCall::GetOSDTEntryInfo as u64,
                 in("rsi") self.0,
                 in("rdx") ty as u64,
-                in("rcx") k.map_or(0, |s| s.as_ptr() as u64),
+                in("r10") k.map_or(0, |s| s.as_ptr() as u64),
                 in("r8") k.map_or(0, |s| s.len() as u64),
                 out("rax") ptr,
                 lateout("rdi") len,
+                out("rcx") _,
+                out("r11") _,
                 options(nostack),
             );
             Vec::from_raw_parts(ptr as *mut u8, len as _, len as _)
@@ -59,10 +61,12 @@ impl OSDTEntry {
         let mut id: u64;
         unsafe {
             core::arch::asm!(
-                "int 249",
+                "syscall",
                 in("rdi") SystemCall::NewOSDTEntry as u64,
                 in("rsi") self.0,
                 out("rax") id,
+                out("rcx") _,
+                out("r11") _,
                 options(nostack),
             );
         }
@@ -97,11 +101,13 @@ impl OSDTEntry {
         let req = postcard::to_allocvec(&OSDTEntryProp(k.to_owned(), v)).unwrap();
         unsafe {
             core::arch::asm!(
-                "int 249",
+                "syscall",
                 in("rdi") SystemCall::SetOSDTEntryProp as u64,
                 in("rsi") self.0,
                 in("rdx") req.as_ptr() as u64,
-                in("rcx") req.len() as u64,
+                in("r10") req.len() as u64,
+                out("rcx") _,
+                out("r11") _,
                 options(nostack),
             );
         }
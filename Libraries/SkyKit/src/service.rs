@@ -0,0 +1,58 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+//! Kernel-side registry mapping service names such as
+//! `org.ChefKiss.PCIKit` to the PID serving them.
+
+use crate::syscall::SystemCall;
+
+/// Publishes the calling process as `name`. Returns `false` if another
+/// live process has it already.
+pub fn register(name: &str) -> bool {
+    let ret: u64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::RegisterService as u64,
+            in("rsi") name.as_ptr() as u64,
+            in("rdx") name.len() as u64,
+            lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    ret != 0
+}
+
+fn lookup_raw(name: &str, wait: bool) -> u64 {
+    let ret: u64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::LookupService as u64,
+            in("rsi") name.as_ptr() as u64,
+            in("rdx") name.len() as u64,
+            in("r10") u64::from(wait),
+            lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    ret
+}
+
+/// PID of the process serving `name`, if any.
+#[must_use]
+pub fn lookup(name: &str) -> Option<u64> {
+    match lookup_raw(name, false) {
+        0 => None,
+        pid => Some(pid),
+    }
+}
+
+/// Like [`lookup`], but blocks until someone registers `name`.
+#[must_use]
+pub fn wait_for(name: &str) -> u64 {
+    lookup_raw(name, true)
+}
@@ -0,0 +1,90 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+//! Memory regions several processes map at once, for passing bulk data
+//! without a message per transfer. The pages live until the creator and
+//! every process mapping them are done with them.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "userspace")]
+use crate::{msg::Endpoint, syscall::SystemCall};
+
+/// The most pages a single region can span, 64 MiB.
+pub const MAX_SHARED_PAGES: u64 = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(transparent)]
+pub struct SharedRegion(pub u64);
+
+#[cfg(feature = "userspace")]
+impl SharedRegion {
+    /// Creates a zeroed region of `page_count` pages. The creator can map it
+    /// writable, anyone else needs a grant. Returns `None` if there is not
+    /// enough memory or `page_count` is over [`MAX_SHARED_PAGES`].
+    #[must_use]
+    pub unsafe fn create(page_count: u64) -> Option<Self> {
+        let id: u64;
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::CreateSharedRegion as u64,
+            in("rsi") page_count,
+            out("rax") id,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        (id != 0).then_some(Self(id))
+    }
+
+    /// Lets `pid` map the region. Only the creator can grant.
+    pub unsafe fn grant(self, pid: u64, writable: bool) {
+        self.grant_raw(pid, Endpoint::DEFAULT, writable);
+    }
+
+    /// Like [`Self::grant`], for the process owning `endpoint`.
+    pub unsafe fn grant_endpoint(self, endpoint: Endpoint, writable: bool) {
+        self.grant_raw(0, endpoint, writable);
+    }
+
+    unsafe fn grant_raw(self, pid: u64, endpoint: Endpoint, writable: bool) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::GrantSharedRegion as u64,
+            in("rsi") self.0,
+            in("rdx") pid,
+            in("r10") endpoint.0,
+            in("r8") u64::from(writable),
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    /// Maps the region into this process. It is read-only unless this
+    /// process created it or got a writable grant.
+    #[must_use]
+    pub unsafe fn map(self) -> *mut [u8] {
+        let (addr, size): (u64, u64);
+        core::arch::asm!(
+            "syscall",
+            inlateout("rdi") SystemCall::MapSharedRegion as u64 => size,
+            in("rsi") self.0,
+            out("rax") addr,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        core::ptr::slice_from_raw_parts_mut(addr as *mut u8, size as _)
+    }
+
+    pub unsafe fn unmap(self) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::UnmapSharedRegion as u64,
+            in("rsi") self.0,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+//! Blocking primitives built on `FutexWait`/`FutexWake`.
+//!
+//! Waiters sleep in the kernel instead of spinning, and since futexes are
+//! keyed on physical memory, they also work in memory shared between processes.
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use crate::syscall::{FutexWaitResult, SystemCall};
+
+fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) -> FutexWaitResult {
+    // Zero means no timeout to the kernel, so round short ones up
+    let timeout_ms = timeout.map_or(0, |v| {
+        v.as_nanos().div_ceil(1_000_000).clamp(1, u64::MAX.into()) as u64
+    });
+    unsafe { SystemCall::futex_wait(word.as_ptr(), expected, timeout_ms) }
+}
+
+fn futex_wake(word: &AtomicU32, count: u64) {
+    unsafe {
+        SystemCall::futex_wake(word.as_ptr(), count);
+    }
+}
+
+/// How often a contended lock gets polled before going to sleep.
+const SPIN_LIMIT: usize = 100;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked and someone might be sleeping on it.
+const CONTENDED: u32 = 2;
+
+pub struct Mutex<T: ?Sized> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        MutexGuard { mutex: self }
+    }
+
+    fn lock_contended(&self) {
+        for _ in 0..SPIN_LIMIT {
+            if self.state.load(Ordering::Relaxed) != LOCKED {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex_wait(&self.state, CONTENDED, None);
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+            .then_some(MutexGuard { mutex: self })
+    }
+
+    pub const fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+#[derive(Default)]
+pub struct Condvar {
+    /// Bumped on every notification, so a wait that races one doesn't sleep.
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    /// Unlocks the mutex and sleeps until notified, then locks it again.
+    /// Spurious wake-ups are possible.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_inner(guard, None).0
+    }
+
+    /// Like [`Self::wait`], the flag is whether `timeout` ran out first.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        self.wait_inner(guard, Some(timeout))
+    }
+
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    fn wait_inner<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<Duration>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+        let res = futex_wait(&self.seq, seq, timeout);
+        (mutex.lock(), res == FutexWaitResult::TimedOut)
+    }
+
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, 1);
+    }
+
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, u64::MAX);
+    }
+}
+
+/// Held by a writer, otherwise the low bits are the number of readers.
+const WRITE_LOCKED: u32 = u32::MAX >> 1;
+/// Someone might be sleeping on the lock, the unlock that frees it wakes them.
+const WAITING: u32 = 1 << 31;
+
+/// A reader-writer lock with no fairness guarantees, readers can starve
+/// writers.
+pub struct RwLock<T: ?Sized> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !WAITING >= WRITE_LOCKED - 1 {
+                self.wait(state);
+            }
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |v| {
+                (v & !WAITING < WRITE_LOCKED - 1).then(|| v + 1)
+            })
+            .is_ok()
+            .then_some(RwLockReadGuard { lock: self })
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !WAITING != 0 {
+                self.wait(state);
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |v| {
+                (v & !WAITING == 0).then_some(v | WRITE_LOCKED)
+            })
+            .is_ok()
+            .then_some(RwLockWriteGuard { lock: self })
+    }
+
+    /// Flags the lock as waited on and sleeps while it stays at `state`.
+    fn wait(&self, state: u32) {
+        if state & WAITING != 0
+            || self
+                .state
+                .compare_exchange(state, state | WAITING, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            futex_wait(&self.state, state | WAITING, None);
+        }
+    }
+
+    pub const fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Waiters only care once the lock is free, the last reader wakes them
+        let prev = self
+            .lock
+            .state
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |v| {
+                Some(if v == WAITING | 1 { 0 } else { v - 1 })
+            })
+            .unwrap();
+        if prev == WAITING | 1 {
+            futex_wake(&self.lock.state, u64::MAX);
+        }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.lock.state.swap(0, Ordering::Release) & WAITING != 0 {
+            futex_wake(&self.lock.state, u64::MAX);
+        }
+    }
+}
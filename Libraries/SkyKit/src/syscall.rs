@@ -10,6 +10,26 @@ pub enum AccessSize {
     DWord,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
+#[repr(u64)]
+pub enum ThreadPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    /// Only for processes handling an IRQ.
+    Realtime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+#[repr(u64)]
+pub enum FutexWaitResult {
+    Woken,
+    /// The word didn't hold the expected value, so the thread never slept.
+    ValueMismatch,
+    TimedOut,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
 #[repr(u64)]
 pub enum SystemCall {
@@ -27,24 +47,154 @@ pub enum SystemCall {
     NewOSDTEntry,
     GetOSDTEntryInfo,
     SetOSDTEntryProp,
+    SetPriority,
+    SpawnThread,
+    ExitThread,
+    JoinThread,
+    Sleep,
+    GetMonotonicTime,
+    GetWallClock,
+    FutexWait,
+    FutexWake,
+    MsgCall,
+    MsgReply,
+    RegisterService,
+    LookupService,
+    CreateEndpoint,
+    DestroyEndpoint,
+    CreateNotification,
+    DestroyNotification,
+    Signal,
+    WaitNotification,
+    BindIRQ,
+    CreateSharedRegion,
+    GrantSharedRegion,
+    MapSharedRegion,
+    UnmapSharedRegion,
+    SetQueueLimit,
+    GetQueueStats,
+    CreateChannel,
+    SubscribeChannel,
+    PublishChannel,
+    MonitorProcess,
+    UnregisterIRQ,
+    DetachThread,
 }
 
 #[cfg(feature = "userspace")]
 impl SystemCall {
-    pub unsafe fn quit() -> ! {
-        core::arch::asm!("int 249", in("rdi") Self::Quit as u64, options(nostack, noreturn));
+    /// Ends the whole process with `code`, all threads included.
+    pub unsafe fn quit(code: u64) -> ! {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") Self::Quit as u64,
+            in("rsi") code,
+            options(nostack, noreturn),
+        );
+    }
+
+    /// Has the kernel send a [`KernelMessage::ProcessExited`] once `pid`
+    /// exits. Returns false if there is no such process (anymore).
+    ///
+    /// [`KernelMessage::ProcessExited`]: crate::msg::KernelMessage::ProcessExited
+    #[must_use]
+    pub unsafe fn monitor_process(pid: u64) -> bool {
+        let ret: u64;
+        core::arch::asm!(
+            "syscall",
+            in("rdi") Self::MonitorProcess as u64,
+            in("rsi") pid,
+            lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        ret != 0
     }
 
     pub unsafe fn r#yield() {
-        core::arch::asm!("int 249", in("rdi") Self::Yield as u64, options(nostack));
+        core::arch::asm!(
+            "syscall",
+            in("rdi") Self::Yield as u64,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
     }
 
+    /// Has IRQ `irq` delivered as [`KernelMessage::IRQFired`]. Other
+    /// processes may handle the same line, it stays masked from when it
+    /// fires until every one of them acknowledged the message.
+    ///
+    /// [`KernelMessage::IRQFired`]: crate::msg::KernelMessage::IRQFired
     pub unsafe fn register_irq_handler(irq: u8) {
         core::arch::asm!(
-            "int 249",
+            "syscall",
             in("rdi") Self::RegisterIRQ as u64,
             in("sil") irq,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    /// Stops handling IRQ `irq`, including through notifications bound to
+    /// it. This also happens on exit.
+    pub unsafe fn unregister_irq_handler(irq: u8) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") Self::UnregisterIRQ as u64,
+            in("rsi") u64::from(irq),
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    /// Sets the priority of the calling thread. Asking for
+    /// [`ThreadPriority::Realtime`] without handling an IRQ gets the process
+    /// killed.
+    pub unsafe fn set_priority(priority: ThreadPriority) {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") Self::SetPriority as u64,
+            in("rsi") priority as u64,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    /// Sleeps as long as `*addr == expected`, at most `timeout_ms` unless zero.
+    pub unsafe fn futex_wait(addr: *const u32, expected: u32, timeout_ms: u64) -> FutexWaitResult {
+        let ret: u64;
+        core::arch::asm!(
+            "syscall",
+            in("rdi") Self::FutexWait as u64,
+            in("rsi") addr,
+            in("rdx") expected,
+            in("r10") timeout_ms,
+            lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+        FutexWaitResult::try_from(ret).unwrap()
+    }
+
+    /// Wakes at most `count` threads sleeping on `addr`, returns how many.
+    pub unsafe fn futex_wake(addr: *const u32, count: u64) -> u64 {
+        let ret: u64;
+        core::arch::asm!(
+            "syscall",
+            in("rdi") Self::FutexWake as u64,
+            in("rsi") addr,
+            in("rdx") count,
+            lateout("rax") ret,
+            out("rcx") _,
+            out("r11") _,
             options(nostack),
         );
+        ret
     }
 }
@@ -0,0 +1,160 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use core::{
+    ops::{Add, AddAssign, Sub, SubAssign},
+    time::Duration,
+};
+
+use crate::syscall::SystemCall;
+
+/// A point on the kernel's monotonic clock, nanosecond resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    #[must_use]
+    pub fn now() -> Self {
+        let nanos: u64;
+        unsafe {
+            core::arch::asm!(
+                "syscall",
+                in("rdi") SystemCall::GetMonotonicTime as u64,
+                out("rax") nanos,
+                out("rcx") _,
+                out("r11") _,
+                options(nostack, preserves_flags),
+            );
+        }
+        Self(nanos)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `earlier` is later than `self`.
+    #[must_use]
+    pub fn duration_since(&self, earlier: Self) -> Duration {
+        self.checked_duration_since(earlier)
+            .expect("supplied instant is later than self")
+    }
+
+    #[must_use]
+    pub fn checked_duration_since(&self, earlier: Self) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
+
+    #[must_use]
+    pub fn saturating_duration_since(&self, earlier: Self) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        Self::now().saturating_duration_since(*self)
+    }
+
+    #[must_use]
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|v| self.0.checked_add(v))
+            .map(Self)
+    }
+
+    #[must_use]
+    pub fn checked_sub(&self, duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|v| self.0.checked_sub(v))
+            .map(Self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Self;
+
+    fn add(self, rhs: Duration) -> Self {
+        self.checked_add(rhs)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Self;
+
+    fn sub(self, rhs: Duration) -> Self {
+        self.checked_sub(rhs)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Self) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// A point on the wall clock, nanoseconds since the Unix epoch. Unlike
+/// [`Instant`] it is only as good as the RTC it was seeded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(u64);
+
+impl SystemTime {
+    pub const UNIX_EPOCH: Self = Self(0);
+
+    #[must_use]
+    pub fn now() -> Self {
+        let nanos: u64;
+        unsafe {
+            core::arch::asm!(
+                "syscall",
+                in("rdi") SystemCall::GetWallClock as u64,
+                out("rax") nanos,
+                out("rcx") _,
+                out("r11") _,
+                options(nostack, preserves_flags),
+            );
+        }
+        Self(nanos)
+    }
+
+    /// Returns `None` if `earlier` is later than `self`.
+    #[must_use]
+    pub fn duration_since(&self, earlier: Self) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
+
+    #[must_use]
+    pub fn elapsed(&self) -> Option<Duration> {
+        Self::now().duration_since(*self)
+    }
+
+    #[must_use]
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|v| self.0.checked_add(v))
+            .map(Self)
+    }
+
+    #[must_use]
+    pub fn checked_sub(&self, duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|v| self.0.checked_sub(v))
+            .map(Self)
+    }
+}
@@ -11,10 +11,12 @@ unsafe impl core::alloc::GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         let mut ptr: u64;
         core::arch::asm!(
-            "int 249",
+            "syscall",
             in("rdi") SystemCall::Allocate as u64,
             in("rsi") layout.pad_to_align().size() as u64,
             out("rax") ptr,
+            out("rcx") _,
+            out("r11") _,
             options(nostack),
         );
         ptr as *mut u8
@@ -26,10 +28,12 @@ unsafe impl core::alloc::GlobalAlloc for Allocator {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
         core::arch::asm!(
-            "int 249",
+            "syscall",
             in("rdi") SystemCall::Free as u64,
             in("rsi") ptr as u64,
             in("rdx") layout.pad_to_align().size() as u64,
+            out("rcx") _,
+            out("r11") _,
             options(nostack),
         );
     }
@@ -14,10 +14,12 @@ impl Write for KWriter {
 
         unsafe {
             core::arch::asm!(
-                "int 249",
+                "syscall",
                 in("rdi") SystemCall::KPrint as u64,
                 in("rsi") s.as_ptr() as u64,
                 in("rdx") s.len() as u64,
+                out("rcx") _,
+                out("r11") _,
                 options(nostack),
             );
         }
@@ -4,3 +4,4 @@ mod allocator;
 pub mod logger;
 mod panic;
 pub mod port;
+pub mod thread;
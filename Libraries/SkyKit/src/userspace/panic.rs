@@ -5,5 +5,6 @@ use crate::syscall::SystemCall;
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
     error!("{info}");
-    unsafe { SystemCall::quit() }
+    // Same as Rust's std
+    unsafe { SystemCall::quit(101) }
 }
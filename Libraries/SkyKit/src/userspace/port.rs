@@ -6,11 +6,13 @@ macro_rules! PortIOSystemCallIn {
     ($out:tt, $port:expr, $size:expr) => {{
         let mut val: Self;
         core::arch::asm!(
-            "int 249",
+            "syscall",
             in("rdi") SystemCall::PortIn as u64,
             in("rsi") $port,
             in("rdx") $size as u64,
             out($out) val,
+            out("rcx") _,
+            out("r11") _,
             options(nostack),
         );
         val
@@ -20,11 +22,13 @@ macro_rules! PortIOSystemCallIn {
 macro_rules! PortIOSystemCallOut {
     ($in_:tt, $port:expr, $value:expr, $size:expr) => {
         core::arch::asm!(
-            "int 249",
+            "syscall",
             in("rdi") SystemCall::PortOut as u64,
             in("rsi") $port,
             in("rdx") $size as u64,
             in($in_) $value,
+            out("rcx") _,
+            out("r11") _,
             options(nostack),
         )
     };
@@ -41,7 +45,7 @@ impl PortIO for u8 {
     }
 
     unsafe fn write(port: u16, value: Self) {
-        PortIOSystemCallOut!("cl", port, value, AccessSize::Byte);
+        PortIOSystemCallOut!("r10b", port, value, AccessSize::Byte);
     }
 }
 
@@ -51,7 +55,7 @@ impl PortIO for u16 {
     }
 
     unsafe fn write(port: u16, value: Self) {
-        PortIOSystemCallOut!("cx", port, value, AccessSize::Word);
+        PortIOSystemCallOut!("r10w", port, value, AccessSize::Word);
     }
 }
 
@@ -61,7 +65,7 @@ impl PortIO for u32 {
     }
 
     unsafe fn write(port: u16, value: Self) {
-        PortIOSystemCallOut!("ecx", port, value, AccessSize::DWord);
+        PortIOSystemCallOut!("r10d", port, value, AccessSize::DWord);
     }
 }
 
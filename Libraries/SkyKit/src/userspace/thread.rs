@@ -0,0 +1,118 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use alloc::{boxed::Box, sync::Arc};
+use core::{cell::UnsafeCell, mem::ManuallyDrop, time::Duration};
+
+use crate::syscall::SystemCall;
+
+type ThreadMain = Box<dyn FnOnce() + Send>;
+
+/// Where the spawned thread leaves its result, only read after joining it.
+struct Packet<T>(UnsafeCell<Option<T>>);
+
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+/// Dropping the handle detaches the thread, which then cleans up after itself.
+pub struct JoinHandle<T> {
+    tid: u64,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    #[must_use]
+    pub const fn id(&self) -> u64 {
+        self.tid
+    }
+
+    /// Blocks until the thread exits and returns what its closure returned.
+    pub fn join(self) -> T {
+        // Joining already frees the thread, don't detach it on top
+        let this = ManuallyDrop::new(self);
+        unsafe {
+            core::arch::asm!(
+                "syscall",
+                in("rdi") SystemCall::JoinThread as u64,
+                in("rsi") this.tid,
+                lateout("rax") _,
+                out("rcx") _,
+                out("r11") _,
+                options(nostack),
+            );
+            let packet = core::ptr::read(&this.packet);
+            (*packet.0.get()).take().unwrap()
+        }
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        unsafe {
+            core::arch::asm!(
+                "syscall",
+                in("rdi") SystemCall::DetachThread as u64,
+                in("rsi") self.tid,
+                out("rcx") _,
+                out("r11") _,
+                options(nostack),
+            );
+        }
+    }
+}
+
+extern "sysv64" fn thread_start(main: *mut ThreadMain) -> ! {
+    unsafe { Box::from_raw(main)() }
+    unsafe { exit(0) }
+}
+
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = Arc::new(Packet(UnsafeCell::new(None)));
+    let theirs = packet.clone();
+    let main: ThreadMain = Box::new(move || unsafe { *theirs.0.get() = Some(f()) });
+    let main = Box::into_raw(Box::new(main));
+
+    let mut tid: u64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::SpawnThread as u64,
+            in("rsi") thread_start as *const () as u64,
+            in("rdx") main as u64,
+            out("rax") tid,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+
+    JoinHandle { tid, packet }
+}
+
+/// Ends the calling thread. The process goes away with its last thread.
+pub unsafe fn exit(code: u64) -> ! {
+    core::arch::asm!(
+        "syscall",
+        in("rdi") SystemCall::ExitThread as u64,
+        in("rsi") code,
+        options(nostack, noreturn),
+    );
+}
+
+/// Blocks the calling thread for at least `duration`, with millisecond
+/// granularity. A zero duration just yields.
+pub fn sleep(duration: Duration) {
+    let ms = duration.as_nanos().div_ceil(1_000_000).min(u64::MAX.into()) as u64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            in("rdi") SystemCall::Sleep as u64,
+            in("rsi") ms,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+}
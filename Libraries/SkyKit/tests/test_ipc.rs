@@ -0,0 +1,17 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use skykit::ipc::InterfaceId;
+
+#[test]
+pub fn interface_id_fnv1a() {
+    assert_eq!(InterfaceId::new("", 1).name_hash, 0xCBF2_9CE4_8422_2325);
+    assert_eq!(InterfaceId::new("a", 1).name_hash, 0xAF63_DC4C_8601_EC8C);
+}
+
+#[test]
+pub fn interface_id_differs() {
+    let id = InterfaceId::new("org.ChefKiss.PCIKit", 1);
+    assert_eq!(id, InterfaceId::new("org.ChefKiss.PCIKit", 1));
+    assert_ne!(id, InterfaceId::new("org.ChefKiss.PCIKit", 2));
+    assert_ne!(id, InterfaceId::new("org.ChefKiss.PCIKit2", 1));
+}
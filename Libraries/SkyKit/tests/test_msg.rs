@@ -0,0 +1,38 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use skykit::msg::{QueueFullPolicy, RecvFilter, SendFlags};
+
+#[test]
+pub fn send_flags_round_trip() {
+    for move_pages in [false, true] {
+        for on_full in [
+            QueueFullPolicy::Block,
+            QueueFullPolicy::Fail,
+            QueueFullPolicy::Drop,
+        ] {
+            let flags = SendFlags {
+                move_pages,
+                on_full,
+            };
+            assert_eq!(SendFlags::from_raw(flags.into_raw()), Some(flags));
+        }
+    }
+}
+
+#[test]
+pub fn send_flags_default_is_zero() {
+    assert_eq!(SendFlags::default().into_raw(), 0);
+    assert_eq!(SendFlags::from_raw(0), Some(SendFlags::default()));
+}
+
+#[test]
+pub fn send_flags_reject_unknown_policy() {
+    assert_eq!(SendFlags::from_raw(3 << 1), None);
+}
+
+#[test]
+pub fn recv_filter_round_trip() {
+    for filter in [RecvFilter::Any, RecvFilter::Kernel, RecvFilter::Pid(42)] {
+        assert_eq!(RecvFilter::from_raw(filter.into_raw()), filter);
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+#![deny(warnings, clippy::nursery, unused_extern_crates)]
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, FnArg, Ident, ItemTrait, LitInt, LitStr, Pat, ReturnType,
+    TraitItem, Type,
+};
+
+struct Method {
+    name: Ident,
+    variant: Ident,
+    args: Vec<Ident>,
+    tys: Vec<Type>,
+    ret: Type,
+}
+
+fn to_camel_case(name: &Ident) -> Ident {
+    let name = name.to_string();
+    let mut ret = String::with_capacity(name.len());
+    for part in name.split('_') {
+        let mut chars = part.chars();
+        if let Some(c) = chars.next() {
+            ret.extend(c.to_uppercase());
+            ret.push_str(chars.as_str());
+        }
+    }
+    Ident::new(&ret, Span::call_site())
+}
+
+fn parse_method(item: &TraitItem) -> syn::Result<Method> {
+    let TraitItem::Fn(func) = item else {
+        return Err(syn::Error::new(
+            item.span(),
+            "Interfaces can only contain methods",
+        ));
+    };
+    let sig = &func.sig;
+    if !sig.generics.params.is_empty() || sig.asyncness.is_some() {
+        return Err(syn::Error::new(
+            sig.span(),
+            "Interface methods cannot be generic or async",
+        ));
+    }
+
+    let mut inputs = sig.inputs.iter();
+    if !matches!(inputs.next(), Some(FnArg::Receiver(v)) if v.reference.is_some()) {
+        return Err(syn::Error::new(
+            sig.span(),
+            "Interface methods must take `self` by reference",
+        ));
+    }
+    let mut args = vec![];
+    let mut tys = vec![];
+    for input in inputs {
+        let FnArg::Typed(arg) = input else {
+            unreachable!();
+        };
+        let Pat::Ident(pat) = &*arg.pat else {
+            return Err(syn::Error::new(
+                arg.pat.span(),
+                "Interface method arguments must be plain identifiers",
+            ));
+        };
+        args.push(pat.ident.clone());
+        tys.push((*arg.ty).clone());
+    }
+
+    Ok(Method {
+        name: sig.ident.clone(),
+        variant: to_camel_case(&sig.ident),
+        args,
+        tys,
+        ret: match &sig.output {
+            ReturnType::Default => syn::parse_quote!(()),
+            ReturnType::Type(_, ty) => (**ty).clone(),
+        },
+    })
+}
+
+/// Turns a trait into a typed IPC interface.
+///
+/// Next to the trait itself, this generates `<Trait>Request`, a serialisable
+/// enum with a variant per method, and `<Trait>Client`, a proxy with the same
+/// methods that calls a peer implementing the trait. Servers pass what they
+/// receive to `<Trait>Request::handle`.
+///
+/// Requests carry the interface ID made of `name` and `version`, so peers
+/// built against another version get an error instead of garbage. Argument
+/// and return types have to implement serde's traits, argument types also
+/// `Debug`.
+///
+/// ```ignore
+/// #[skykit::ipc::interface(name = "org.ChefKiss.Example", version = 1)]
+/// pub trait Example {
+///     fn add(&mut self, a: u32, b: u32) -> u32;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn interface(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name: Option<LitStr> = None;
+    let mut version: Option<LitInt> = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("version") {
+            version = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("Expected `name` or `version`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let item = parse_macro_input!(item as ItemTrait);
+    let (Some(name), Some(version)) = (name, version) else {
+        return syn::Error::new(item.span(), "Interfaces need a `name` and a `version`")
+            .to_compile_error()
+            .into();
+    };
+
+    let methods = match item
+        .items
+        .iter()
+        .map(parse_method)
+        .collect::<syn::Result<Vec<_>>>()
+    {
+        Ok(v) => v,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let vis = &item.vis;
+    let trait_name = &item.ident;
+    let request = format_ident!("{trait_name}Request");
+    let client = format_ident!("{trait_name}Client");
+
+    let variants = methods.iter().map(|m| {
+        let (variant, tys) = (&m.variant, &m.tys);
+        if tys.is_empty() {
+            quote!(#variant)
+        } else {
+            quote!(#variant(#(#tys),*))
+        }
+    });
+    let arms = methods.iter().map(|m| {
+        let (name, variant, args) = (&m.name, &m.variant, &m.args);
+        let pat = if args.is_empty() {
+            quote!(Self::#variant)
+        } else {
+            quote!(Self::#variant(#(#args),*))
+        };
+        quote!(#pat => ::skykit::ipc::reply(msg, Ok(server.#name(#(#args),*))))
+    });
+    let calls = methods.iter().map(|m| {
+        let (name, variant, args, tys, ret) = (&m.name, &m.variant, &m.args, &m.tys, &m.ret);
+        let req = if args.is_empty() {
+            quote!(#request::#variant)
+        } else {
+            quote!(#request::#variant(#(#args),*))
+        };
+        quote! {
+            pub fn #name(&self, #(#args: #tys),*) -> Result<#ret, ::skykit::ipc::IpcError> {
+                ::skykit::ipc::call(self.peer, #request::INTERFACE, &#req)
+            }
+        }
+    });
+
+    quote! {
+        #item
+
+        #[derive(Debug, ::skykit::serde::Serialize, ::skykit::serde::Deserialize)]
+        #[serde(crate = "::skykit::serde")]
+        #vis enum #request {
+            #(#variants),*
+        }
+
+        impl #request {
+            pub const INTERFACE: ::skykit::ipc::InterfaceId =
+                ::skykit::ipc::InterfaceId::new(#name, #version);
+
+            /// Runs the request in `msg` on `server` and answers the caller.
+            pub fn handle<S: #trait_name + ?Sized>(server: &mut S, msg: &mut ::skykit::msg::Message) {
+                let Some(req) = ::skykit::ipc::decode_request::<Self>(Self::INTERFACE, msg) else {
+                    return;
+                };
+                match req {
+                    #(#arms),*
+                }
+            }
+        }
+
+        #[derive(Debug, Clone, Copy)]
+        #vis struct #client {
+            peer: ::skykit::ipc::Peer,
+        }
+
+        impl #client {
+            #[must_use]
+            pub const fn new(peer: ::skykit::ipc::Peer) -> Self {
+                Self { peer }
+            }
+
+            #(#calls)*
+        }
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::Span;
+    use syn::Ident;
+
+    use super::to_camel_case;
+
+    fn camel(name: &str) -> String {
+        to_camel_case(&Ident::new(name, Span::call_site())).to_string()
+    }
+
+    #[test]
+    fn camel_case() {
+        assert_eq!(camel("read"), "Read");
+        assert_eq!(camel("read_config"), "ReadConfig");
+        assert_eq!(camel("set_bar_2"), "SetBar2");
+        assert_eq!(camel("already_Camel"), "AlreadyCamel");
+        assert_eq!(camel("_leading__double_"), "LeadingDouble");
+    }
+}
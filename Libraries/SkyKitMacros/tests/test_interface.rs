@@ -0,0 +1,6 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+#[test]
+pub fn interface_rejects() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,8 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+#[skykit_macros::interface(name = "org.ChefKiss.Test", version = 1)]
+pub trait Test {
+    fn ping(&self, (a, b): (u32, u32));
+}
+
+fn main() {}
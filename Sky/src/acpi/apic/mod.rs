@@ -1,7 +1,7 @@
 // Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
 
 use amd64::{
-    msr::{apic::APICBase, ModelSpecificReg},
+    msr::{apic::APICBase, tsc_deadline::TscDeadline, ModelSpecificReg},
     paging::PageTableFlags,
 };
 use num_enum::IntoPrimitive;
@@ -14,6 +14,14 @@ pub struct LocalAPIC {
     addr: u64,
 }
 
+/// Per-core result of [`LocalAPIC::setup_timer`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimerCalibration {
+    pub lapic_ticks_per_ms: u64,
+    pub tsc_ticks_per_ms: u64,
+    pub tsc_deadline: bool,
+}
+
 #[derive(Debug, IntoPrimitive)]
 #[repr(u64)]
 pub enum LocalAPICReg {
@@ -190,6 +198,10 @@ impl LocalAPIC {
         self.read_reg(LocalAPICReg::Ver)
     }
 
+    pub fn id(&self) -> u8 {
+        (self.read_reg::<_, u32>(LocalAPICReg::ID) >> 24) as u8
+    }
+
     pub fn send_eoi(&self) {
         self.write_reg(LocalAPICReg::EndOfInterrupt, 0u32);
     }
@@ -241,6 +253,19 @@ impl LocalAPIC {
         self.read_reg(LocalAPICReg::ErrorStatus)
     }
 
+    pub fn send_ipi(&self, cmd: InterruptCommand) {
+        let value = u64::from(cmd);
+        self.write_reg(LocalAPICReg::InterruptCommand2, (value >> 32) as u32);
+        self.write_reg(LocalAPICReg::InterruptCommand, value as u32);
+        while InterruptCommand::from(u64::from(
+            self.read_reg::<_, u32>(LocalAPICReg::InterruptCommand),
+        ))
+        .delivery_pending()
+        {
+            core::hint::spin_loop();
+        }
+    }
+
     pub fn write_spurious_intr_vec(&self, val: SpuriousIntrVector) {
         self.write_reg(LocalAPICReg::SpuriousInterruptVector, val);
     }
@@ -253,23 +278,108 @@ impl LocalAPIC {
         );
     }
 
-    pub fn setup_timer(&self, timer: &impl crate::timer::Timer) {
-        self.set_timer_divide(0x3);
-        self.set_timer_init_count(0xFFFF_FFFF);
+    /// Programs the LAPIC of the calling core. Only the BSP gets the legacy
+    /// PIC virtual wire on LINT0, the APs leave it masked.
+    pub fn init_core(&self, bsp: bool) {
+        let ver = self.read_ver();
+
+        // Do not trust LAPIC to be empty at boot
+        if ver.max_lvt_entry() > 2 {
+            self.write_reg(
+                LocalAPICReg::LVTError,
+                lvt::LocalVectorTable::new().with_mask(true),
+            );
+        }
 
-        self.write_timer(self.read_timer().with_mask(false));
-        timer.sleep(10);
         self.write_timer(self.read_timer().with_mask(true));
+        self.write_lint(false, self.read_lint(false).with_mask(true));
+        self.write_lint(true, self.read_lint(true).with_mask(true));
+        if ver.max_lvt_entry() > 3 {
+            self.write_reg(
+                LocalAPICReg::LVTPerfCounter,
+                lvt::LocalVectorTable::new().with_mask(true),
+            );
+        }
 
-        let ticks_per_ms = (0xFFFF_FFFF - self.read_timer_counter()) / 10;
+        if ver.max_lvt_entry() > 4 {
+            self.write_reg(
+                LocalAPICReg::LVTThermalSensor,
+                lvt::LocalVectorTable::new().with_mask(true),
+            );
+        }
+
+        self.enable();
+
+        // Set up virtual wire
+        if bsp {
+            self.write_lint(
+                false,
+                lvt::LocalVectorTable::new().with_delivery_mode(DeliveryMode::ExtInt),
+            );
+        }
+        self.write_lint(
+            true,
+            lvt::LocalVectorTable::new().with_delivery_mode(DeliveryMode::Nmi),
+        );
+
+        if ver.max_lvt_entry() > 2 {
+            self.write_reg(
+                LocalAPICReg::LVTError,
+                lvt::LocalVectorTable::new().with_vector(0xFE),
+            );
+        }
+    }
+
+    /// Measures the LAPIC timer and TSC rates against `timer`, then leaves
+    /// the LAPIC timer masked in one-shot or TSC-deadline mode.
+    pub fn setup_timer(&self, timer: &impl crate::timer::Timer) -> TimerCalibration {
         self.write_timer(
             lvt::TimerLVT::new()
                 .with_vector(128)
                 .with_mask(true)
-                .with_mode(lvt::TimerMode::Periodic),
+                .with_mode(lvt::TimerMode::OneShot),
         );
         self.set_timer_divide(0x3);
-        self.set_timer_init_count(ticks_per_ms);
+        self.set_timer_init_count(0xFFFF_FFFF);
+
+        let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+        timer.sleep(10);
+        let tsc_ticks_per_ms = (unsafe { core::arch::x86_64::_rdtsc() } - tsc) / 10;
+        let lapic_ticks_per_ms = u64::from(0xFFFF_FFFF - self.read_timer_counter()) / 10;
+        self.set_timer_init_count(0);
+
+        let tsc_deadline = amd64::cpuid::CPUIdentification::new()
+            .features
+            .tsc_deadline();
+        if tsc_deadline {
+            self.write_timer(self.read_timer().with_mode(lvt::TimerMode::TscDeadline));
+        }
+
+        TimerCalibration {
+            lapic_ticks_per_ms,
+            tsc_ticks_per_ms,
+            tsc_deadline,
+        }
+    }
+
+    /// Makes the timer fire once the TSC reaches `deadline`, or stops it.
+    pub fn arm_timer(&self, cal: &TimerCalibration, deadline: Option<u64>) {
+        if cal.tsc_deadline {
+            unsafe {
+                TscDeadline::new()
+                    .with_deadline(deadline.unwrap_or(0))
+                    .write()
+            }
+            return;
+        }
+
+        let count = deadline.map_or(0, |v| {
+            let delta = v.saturating_sub(unsafe { core::arch::x86_64::_rdtsc() });
+            (u128::from(delta) * u128::from(cal.lapic_ticks_per_ms)
+                / u128::from(cal.tsc_ticks_per_ms))
+            .clamp(1, u32::MAX.into()) as u32
+        });
+        self.set_timer_init_count(count);
     }
 }
 
@@ -322,12 +432,7 @@ pub fn setup(state: &mut crate::system::state::SystemState) {
     let ver = lapic.read_ver();
     debug!("LAPIC version is {ver:#X?}");
 
-    // Do not trust LAPIC to be empty at boot
     if ver.max_lvt_entry() > 2 {
-        lapic.write_reg(
-            LocalAPICReg::LVTError,
-            lvt::LocalVectorTable::new().with_mask(true),
-        );
         crate::interrupts::idt::set_handler(
             0xFE,
             0,
@@ -337,26 +442,6 @@ pub fn setup(state: &mut crate::system::state::SystemState) {
             true,
         );
     }
-
-    lapic.write_timer(lapic.read_timer().with_mask(true));
-    lapic.write_lint(false, lapic.read_lint(false).with_mask(true));
-    lapic.write_lint(true, lapic.read_lint(true).with_mask(true));
-    if ver.max_lvt_entry() > 3 {
-        lapic.write_reg(
-            LocalAPICReg::LVTPerfCounter,
-            lvt::LocalVectorTable::new().with_mask(true),
-        );
-    }
-
-    if ver.max_lvt_entry() > 4 {
-        lapic.write_reg(
-            LocalAPICReg::LVTThermalSensor,
-            lvt::LocalVectorTable::new().with_mask(true),
-        );
-    }
-
-    lapic.enable();
-
     crate::interrupts::idt::set_handler(
         0xFD,
         0,
@@ -366,22 +451,7 @@ pub fn setup(state: &mut crate::system::state::SystemState) {
         true,
     );
 
-    // Set up virtual wire
-    lapic.write_lint(
-        false,
-        lvt::LocalVectorTable::new().with_delivery_mode(DeliveryMode::ExtInt),
-    );
-    lapic.write_lint(
-        true,
-        lvt::LocalVectorTable::new().with_delivery_mode(DeliveryMode::Nmi),
-    );
-
-    if ver.max_lvt_entry() > 2 {
-        lapic.write_reg(
-            LocalAPICReg::LVTError,
-            lvt::LocalVectorTable::new().with_vector(0xFE),
-        );
-    }
-
+    lapic.init_core(true);
     state.lapic = Some(lapic);
+    crate::system::percpu::PerCpu::init();
 }
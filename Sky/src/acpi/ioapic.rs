@@ -39,15 +39,44 @@ pub fn wire_legacy_irq(irq: u8, masked: bool) {
     );
 }
 
+/// Like [`wire_legacy_irq`], but lines past the ISA ones without an
+/// override are taken for the PCI interrupts they are, which are shared and
+/// thus level-triggered and active-low.
+pub fn wire_irq(irq: u8, masked: bool) {
+    let state = unsafe { &mut *crate::system::state::SYS_STATE.get() };
+    let madt = state.madt.as_ref().unwrap().lock();
+    if irq < 16 || madt.isos.iter().any(|v| v.irq == irq) {
+        drop(madt);
+        wire_legacy_irq(irq, masked);
+        return;
+    }
+    let gsi = u32::from(irq);
+    let Some(ioapic) = find_for_gsi(&madt, gsi) else {
+        warn!("No I/O APIC handles gsi {gsi}");
+        return;
+    };
+    debug!("Routing irq {irq} to I/O APIC {} at gsi {gsi}", ioapic.id);
+    ioapic.write_redir(
+        gsi - ioapic.gsi_base,
+        IOAPICRedir::new()
+            .with_vector(irq + 0x20)
+            .with_active_high(false)
+            .with_trigger_at_level(true)
+            .with_masked(masked),
+    );
+}
+
 pub fn set_irq_mask(irq: u8, masked: bool) {
     let state = unsafe { &mut *crate::system::state::SYS_STATE.get() };
     let madt = state.madt.as_ref().unwrap().lock();
     madt.isos.iter().find(|v| v.irq == irq).map_or_else(
         || {
-            let ioapic = find_for_gsi(&madt, 0).unwrap();
+            let gsi = u32::from(irq);
+            let ioapic =
+                find_for_gsi(&madt, gsi).unwrap_or_else(|| find_for_gsi(&madt, 0).unwrap());
             ioapic.write_redir(
-                u32::from(irq),
-                ioapic.read_redir(u32::from(irq)).with_masked(masked),
+                gsi - ioapic.gsi_base,
+                ioapic.read_redir(gsi - ioapic.gsi_base).with_masked(masked),
             );
         },
         |v| {
@@ -45,19 +45,17 @@ impl ACPIState {
     }
 }
 
-pub fn get_hpet(state: &crate::system::state::SystemState) -> super::timer::hpet::Hpet {
+pub fn get_hpet(state: &crate::system::state::SystemState) -> Option<super::timer::hpet::Hpet> {
     let acpi = state.acpi.as_ref().unwrap();
     let pml4 = state.pml4.as_ref().unwrap();
 
-    acpi.find("HPET")
-        .map(|v: &Hpet| unsafe {
-            pml4.lock().map_mmio(
-                v.address.address + amd64::paging::PHYS_VIRT_OFFSET,
-                v.address.address,
-                1,
-                PageTableFlags::new_present().with_writable(true),
-            );
-            super::timer::hpet::Hpet::new(v)
-        })
-        .unwrap()
+    acpi.find("HPET").map(|v: &Hpet| unsafe {
+        pml4.lock().map_mmio(
+            v.address.address + amd64::paging::PHYS_VIRT_OFFSET,
+            v.address.address,
+            1,
+            PageTableFlags::new_present().with_writable(true),
+        );
+        super::timer::hpet::Hpet::new(v)
+    })
 }
@@ -0,0 +1,64 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+#![allow(dead_code)]
+
+/// Only the ACPI 1.0 part of the table up to the flags, which is all Sky needs.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct FixedACPIDescTable {
+    header: super::SystemDescTableHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    __: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_int: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_req: u8,
+    pub pstate_cnt: u8,
+    pub pm1a_evt_blk: u32,
+    pub pm1b_evt_blk: u32,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    pub pm2_cnt_blk: u32,
+    pub pm_tmr_blk: u32,
+    pub gpe0_blk: u32,
+    pub gpe1_blk: u32,
+    pub pm1_evt_len: u8,
+    pub pm1_cnt_len: u8,
+    pub pm2_cnt_len: u8,
+    pub pm_tmr_len: u8,
+    pub gpe0_blk_len: u8,
+    pub gpe1_blk_len: u8,
+    pub gpe1_base: u8,
+    pub cst_cnt: u8,
+    pub p_lvl2_lat: u16,
+    pub p_lvl3_lat: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alarm: u8,
+    pub month_alarm: u8,
+    century: u8,
+    pub iapc_boot_arch: u16,
+    ___: u8,
+    pub flags: u32,
+}
+
+impl FixedACPIDescTable {
+    /// CMOS index of the RTC century register, if the firmware has one.
+    pub fn century_reg(&self) -> Option<u8> {
+        let end = core::mem::offset_of!(Self, century) + 1;
+        (self.length() >= end && self.century != 0).then_some(self.century)
+    }
+}
+
+impl core::ops::Deref for FixedACPIDescTable {
+    type Target = super::SystemDescTableHeader;
+
+    fn deref(&self) -> &Self::Target {
+        &self.header
+    }
+}
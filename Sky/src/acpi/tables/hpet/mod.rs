@@ -105,6 +105,34 @@ impl Hpet {
     pub fn set_config(&self, value: regs::GeneralConfiguration) {
         self.write_reg(regs::HPETReg::GeneralConfiguration, value.into());
     }
+
+    const fn timer_reg(reg: regs::HPETReg, n: u8) -> u64 {
+        reg as u64 + regs::TIMER_N_STRIDE * n as u64
+    }
+
+    pub fn timer_config(&self, n: u8) -> regs::TimerCfgAndCapability {
+        self.read_reg(Self::timer_reg(regs::HPETReg::TimerNConfig, n))
+            .into()
+    }
+
+    pub fn set_timer_config(&self, n: u8, value: regs::TimerCfgAndCapability) {
+        self.write_reg(
+            Self::timer_reg(regs::HPETReg::TimerNConfig, n),
+            value.into(),
+        );
+    }
+
+    pub fn set_timer_comparator(&self, n: u8, value: u64) {
+        self.write_reg(Self::timer_reg(regs::HPETReg::TimerNComparator, n), value);
+    }
+
+    /// `address` and `data` of the MSI the comparator sends when FSB delivery is on.
+    pub fn set_timer_fsb_route(&self, n: u8, address: u32, data: u32) {
+        self.write_reg(
+            Self::timer_reg(regs::HPETReg::TimerNFSBInterruptRoute, n),
+            (u64::from(address) << 32) | u64::from(data),
+        );
+    }
 }
 
 impl core::ops::Deref for Hpet {
@@ -11,9 +11,12 @@ pub enum HPETReg {
     MainCounterValue = 0x0F0,
     TimerNConfig = 0x100,
     TimerNComparator = 0x108,
-    TimerNFSBInterruptRoute,
+    TimerNFSBInterruptRoute = 0x110,
 }
 
+/// Distance between the register blocks of consecutive comparators.
+pub const TIMER_N_STRIDE: u64 = 0x20;
+
 #[bitfield(u64)]
 pub struct GeneralCapabilities {
     pub rev_id: u8,
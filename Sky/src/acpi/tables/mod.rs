@@ -1,5 +1,6 @@
 // Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
 
+pub mod fadt;
 pub mod hpet;
 pub mod madt;
 pub mod rsdp;
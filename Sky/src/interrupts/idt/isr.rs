@@ -5,6 +5,11 @@ macro_rules! isr_stub {
         core::arch::naked_asm!(
             $err,
             "cld",
+            // Coming from user mode, swap in the per-CPU block
+            "test qword ptr [rsp + 16], 3",
+            "jz 2f",
+            "swapgs",
+            "2:",
             "push {}",
             "push rax",
             "push rbx",
@@ -39,6 +44,10 @@ macro_rules! isr_stub {
             "pop rbx",
             "pop rax",
             "add rsp, 16",
+            "test qword ptr [rsp + 8], 3",
+            "jz 3f",
+            "swapgs",
+            "3:",
             "iretq",
             const $i,
             sym isr_handler,
@@ -51,8 +60,7 @@ unsafe extern "sysv64" fn isr_handler(regs: &mut crate::system::RegisterState) {
     let handler = &(*super::HANDLERS.get())[n as usize];
     (handler.func)(regs);
     if handler.is_irq {
-        let state = &mut *crate::system::state::SYS_STATE.get();
-        state.lapic.as_ref().unwrap().send_eoi();
+        crate::system::percpu::PerCpu::current().lapic.send_eoi();
     }
     if !handler.should_iret && !handler.is_irq {
         crate::hlt_loop!();
@@ -10,9 +10,12 @@ impl log::Log for Logger {
     }
 
     fn log(&self, record: &log::Record) {
+        let us = crate::timer::peek_monotonic_ns() / 1000;
         writeln!(
             crate::system::serial::SERIAL.lock(),
-            "{} {} > {}",
+            "[{:>5}.{:06}] {} {} > {}",
+            us / 1_000_000,
+            us % 1_000_000,
             record.level(),
             record.target(),
             record.args()
@@ -142,8 +142,11 @@ extern "C" fn kernel_main(boot_info: &'static skyliftkit::BootInfo) -> ! {
 
     let fkcache: SKExtensions = postcard::from_bytes(boot_info.fkcache).unwrap();
     state.fkcache = Some(fkcache.into());
-    state.scheduler =
-        Some(system::tasking::scheduler::Scheduler::new(&acpi::get_hpet(state)).into());
+    let timer = timer::SystemTimer::select(state);
+    timer::init_clocksource(state, &timer);
+    timer::rtc::init_wall_clock(state);
+    state.scheduler = Some(system::tasking::scheduler::Scheduler::new(&timer).into());
+    system::smp::start_aps(state, &timer);
 
     system::fkext::spawn_initial_matches();
 
@@ -9,11 +9,13 @@ macro_rules! exception_msg {
         let sys_state = &mut *crate::system::state::SYS_STATE.get();
 
         if $regs.cs.trailing_zeros() >= 2 {
-            sys_state.interrupt_context = Some(*$regs);
+            if let Some(cpu) = crate::system::percpu::PerCpu::get() {
+                cpu.interrupt_context = Some(*$regs);
+            }
             panic!("Received {} exception: {}", $name, $msg);
         } else {
             use core::fmt::Write;
-            let mut scheduler = sys_state.scheduler.as_ref().unwrap().lock();
+            let mut scheduler = crate::system::tasking::scheduler::lock();
             let cur_proc = scheduler.current_process().unwrap();
             let image_base = cur_proc.image_base;
             let proc_path = &cur_proc.path;
@@ -45,7 +47,7 @@ macro_rules! exception_msg {
                 }
             }
 
-            scheduler.process_teardown();
+            scheduler.process_teardown(skykit::ExitReason::Exception($regs.int_num as u8));
             scheduler.schedule($regs);
         }
     };
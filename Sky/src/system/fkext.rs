@@ -6,6 +6,7 @@ use core::hash::Hash;
 use hashbrown::HashMap;
 use skykit::{
     osdtentry::{OSDTENTRY_NAME_KEY, SKEXT_MATCH_KEY, SKEXT_PROC_KEY},
+    osvalue::OSValue,
     SKExtension,
 };
 
@@ -98,7 +99,7 @@ pub fn spawn_initial_matches() {
 
     let dt_index = state.dt_index.as_ref().unwrap();
     let mut dt_id_gen = state.dt_id_gen.as_ref().unwrap().lock();
-    let mut scheduler = state.scheduler.as_ref().unwrap().lock();
+    let mut scheduler = super::tasking::scheduler::lock();
 
     let mut newly_matched = vec![];
     for ((info, payload), mut ent) in iproduct!(
@@ -123,3 +124,93 @@ pub fn spawn_initial_matches() {
     }
     dt_index.write().extend(newly_matched);
 }
+
+/// Whether entry `id` or one below it was loaded for an extension other than
+/// the one running as `proc`.
+fn holds_other(
+    dt_index: &HashMap<u64, spin::Mutex<super::state::OSDTEntry>>,
+    id: u64,
+    proc: &OSValue,
+) -> bool {
+    let Some(ent) = dt_index.get(&id) else {
+        return false;
+    };
+    let ent = ent.lock();
+    if ent
+        .properties
+        .get(SKEXT_PROC_KEY)
+        .is_some_and(|v| v != proc)
+    {
+        return true;
+    }
+    ent.children
+        .iter()
+        .any(|v| holds_other(dt_index, v.into(), proc))
+}
+
+/// Drops the entries of the extensions that ran as `pid` together with
+/// what they published below them. Entries leading to other extensions,
+/// which still hold their IDs, are kept and moved to the parent instead.
+/// The personalities they matched become free to load again on the next
+/// change to their parents.
+///
+/// Returns the PID of the extension that published the entry `pid` was
+/// loaded for, if any.
+pub fn process_exited(pid: u64) -> Option<u64> {
+    let state = unsafe { &*super::state::SYS_STATE.get() };
+
+    let mut dt_index = state.dt_index.as_ref().unwrap().write();
+    let mut dt_id_gen = state.dt_id_gen.as_ref().unwrap().lock();
+
+    let proc = OSValue::from(pid);
+    let owned: Vec<_> = dt_index
+        .iter()
+        .filter(|(_, v)| v.lock().properties.get(SKEXT_PROC_KEY) == Some(&proc))
+        .map(|(id, _)| *id)
+        .collect();
+    let mut publisher = None;
+    for id in owned {
+        let Some(ent) = dt_index.remove(&id) else {
+            continue;
+        };
+        trace!("Dropping OSDT entry <{id}> of PID {pid}");
+        dt_id_gen.free(id);
+        let ent = ent.into_inner();
+        let parent = ent.parent.and_then(|v| dt_index.get::<u64>(&v.into()));
+        if let Some(parent) = parent {
+            parent.lock().children.retain(|v| u64::from(v) != id);
+        }
+
+        let mut next = ent.parent;
+        while let Some(v) = next.and_then(|v| dt_index.get::<u64>(&v.into())) {
+            let v = v.lock();
+            if let Some(&OSValue::U64(v)) = v.properties.get(SKEXT_PROC_KEY) {
+                publisher.get_or_insert(v);
+                break;
+            }
+            next = v.parent;
+        }
+
+        let (kept, mut stale): (Vec<_>, Vec<_>) = ent
+            .children
+            .into_iter()
+            .partition(|v| holds_other(&dt_index, v.into(), &proc));
+        for child in kept {
+            trace!("Moving OSDT entry <{}> up from <{id}>", u64::from(&child));
+            dt_index[&u64::from(&child)].lock().parent = ent.parent;
+            if let Some(parent) = ent.parent.and_then(|v| dt_index.get::<u64>(&v.into())) {
+                parent.lock().children.push(child);
+            }
+        }
+        while let Some(id) = stale.pop().map(u64::from) {
+            let Some(ent) = dt_index.remove(&id) else {
+                continue;
+            };
+            trace!("Dropping OSDT entry <{id}> of PID {pid}");
+            dt_id_gen.free(id);
+            stale.extend(ent.into_inner().children);
+        }
+    }
+
+    publisher
+}
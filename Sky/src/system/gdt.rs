@@ -176,8 +176,9 @@ pub struct GDTData {
     _null: SegmentDescriptor,
     _code_segment: SegmentDescriptor,
     _data_segment: SegmentDescriptor,
-    _user_code_segment: SegmentDescriptor,
+    // `sysret` needs user data right before user code
     _user_data_segment: SegmentDescriptor,
+    _user_code_segment: SegmentDescriptor,
     pub task_segment: TaskSegmentDescriptor,
 }
 
@@ -194,17 +195,26 @@ impl GDTData {
                 DescriptorType::DataSegment,
                 PrivilegeLevel::Supervisor,
             ),
-            _user_code_segment: SegmentDescriptor::new_from_ty(
-                DescriptorType::CodeSegment,
-                PrivilegeLevel::User,
-            ),
             _user_data_segment: SegmentDescriptor::new_from_ty(
                 DescriptorType::DataSegment,
                 PrivilegeLevel::User,
             ),
+            _user_code_segment: SegmentDescriptor::new_from_ty(
+                DescriptorType::CodeSegment,
+                PrivilegeLevel::User,
+            ),
             task_segment: TaskSegmentDescriptor::null(),
         }
     }
+
+    pub fn set_tss(&mut self, tss: &'static super::tss::TaskSegmentSelector) {
+        let tss_addr = tss as *const _ as u64;
+        self.task_segment.base_low = tss_addr as u16;
+        self.task_segment.base_middle = (tss_addr >> 16) as u8;
+        self.task_segment.attrs = self.task_segment.attrs.with_present(true);
+        self.task_segment.base_high = (tss_addr >> 24) as u8;
+        self.task_segment.base_upper = (tss_addr >> 32) as u32;
+    }
 }
 
 pub static GDT: SyncUnsafeCell<GDTData> = SyncUnsafeCell::new(GDTData::new());
@@ -223,6 +233,22 @@ pub struct GDTReg {
 unsafe impl Sync for GDTReg {}
 
 impl GDTReg {
+    #[inline]
+    pub const fn new(gdt: *const GDTData) -> Self {
+        Self {
+            limit: (core::mem::size_of::<GDTData>() - 1) as u16,
+            addr: gdt,
+        }
+    }
+
+    pub unsafe fn load_tss() {
+        core::arch::asm!(
+            "ltr ax",
+            in("ax") SegmentSelector::new(5, PrivilegeLevel::Supervisor).0,
+            options(nostack, preserves_flags),
+        );
+    }
+
     pub unsafe fn load(&self) {
         debug!("Initialising.");
         core::arch::asm!(
@@ -5,11 +5,14 @@ pub mod exceptions;
 pub mod fkext;
 pub mod gdt;
 mod panic;
+pub mod percpu;
 pub mod pmm;
 pub mod serial;
+pub mod smp;
 pub mod state;
 pub mod tasking;
 pub mod terminal;
+pub mod tlb;
 pub mod tss;
 pub mod vmm;
 
@@ -54,6 +54,12 @@ pub fn panic(info: &core::panic::PanicInfo) -> ! {
         .store(true, core::sync::atomic::Ordering::Relaxed);
 
     error!("{info}");
+    if state.wall_clock_offset != 0 {
+        error!(
+            "At {}",
+            crate::timer::rtc::DateTime::from_unix(crate::timer::wall_clock_ns() / 1_000_000_000)
+        );
+    }
     error!("Backtrace:");
     let mut data = CallbackData {
         counter: 0,
@@ -61,7 +67,7 @@ pub fn panic(info: &core::panic::PanicInfo) -> ! {
     };
     _Unwind_Backtrace(callback, core::ptr::addr_of_mut!(data).cast());
 
-    if let Some(ctx) = state.interrupt_context {
+    if let Some(ctx) = super::percpu::PerCpu::get().and_then(|v| v.interrupt_context) {
         data.counter = 0;
         error!("In interrupt:");
         error!("    {ctx}");
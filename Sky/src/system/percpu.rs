@@ -0,0 +1,88 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use amd64::msr::{
+    seg_base::{GsBase, KernelGsBase},
+    ModelSpecificReg,
+};
+
+use super::{tss::TaskSegmentSelector, RegisterState};
+use crate::{
+    acpi::apic::{LocalAPIC, TimerCalibration},
+    timer::hpet::Comparator,
+};
+
+/// Per-core state, reachable through `IA32_GS_BASE` while in kernel mode.
+/// User mode runs with the pointer parked in `IA32_KERNEL_GS_BASE`, every
+/// entry from and exit to CPL 3 does a `swapgs`.
+#[repr(C)]
+pub struct PerCpu {
+    /// Must stay the first field, `gs:[0]` is how [`Self::current`] finds the block.
+    this: *mut Self,
+    /// Scratch slot for the user stack pointer on system call entry.
+    pub user_rsp: u64,
+    /// Top of [`Self::kern_stack`], loaded on system call entry.
+    pub kern_rsp: u64,
+    pub id: u8,
+    pub lapic: &'static LocalAPIC,
+    pub timer_cal: TimerCalibration,
+    /// Used instead of the LAPIC timer if that one stops in deep C-states.
+    pub clock_event: Option<Comparator>,
+    /// TSC value at which the running thread's time slice ends.
+    pub slice_end: u64,
+    pub tss: Option<&'static TaskSegmentSelector>,
+    pub kern_stack: Vec<u8>,
+    pub current_tid: Option<u64>,
+    pub current_pid: Option<u64>,
+    pub interrupt_context: Option<RegisterState>,
+}
+
+impl PerCpu {
+    /// Allocates the calling core's block and points GS at it.
+    pub fn init() -> &'static mut Self {
+        let lapic = unsafe { (*super::state::SYS_STATE.get()).lapic.as_ref().unwrap() };
+        let this = Box::leak(Box::new(Self {
+            this: core::ptr::null_mut(),
+            user_rsp: 0,
+            kern_rsp: 0,
+            id: lapic.id(),
+            lapic,
+            timer_cal: TimerCalibration::default(),
+            clock_event: None,
+            slice_end: 0,
+            tss: None,
+            kern_stack: Vec::new(),
+            current_tid: None,
+            current_pid: None,
+            interrupt_context: None,
+        }));
+        this.this = this;
+
+        unsafe {
+            GsBase::new().with_base(this.this as u64).write();
+            KernelGsBase::new().write();
+        }
+        this
+    }
+
+    /// Only valid in kernel mode after [`Self::init`] ran on this core.
+    #[inline]
+    pub fn current() -> &'static mut Self {
+        unsafe {
+            let this: *mut Self;
+            core::arch::asm!("mov {}, gs:[0]", out(reg) this, options(nostack, readonly, preserves_flags));
+            &mut *this
+        }
+    }
+
+    /// Like [`Self::current`], but safe to call before [`Self::init`], e.g.
+    /// from exception handlers.
+    pub fn get() -> Option<&'static mut Self> {
+        let base = unsafe { GsBase::read() }.base();
+        if base == 0 {
+            return None;
+        }
+        Some(unsafe { &mut *(base as *mut Self) })
+    }
+}
@@ -79,6 +79,13 @@ impl BitmapAllocator {
             free_pages += count;
         }
 
+        // The AP trampoline gets copied there
+        let page = crate::system::smp::TRAMPOLINE_ADDR / PAGE_SIZE;
+        if !crate::bitmap::bit_test(bitmap, page) {
+            crate::bitmap::bit_set(bitmap, page);
+            free_pages -= 1;
+        }
+
         let total_pages = highest_addr / PAGE_SIZE;
         Self {
             bitmap,
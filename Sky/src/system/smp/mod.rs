@@ -0,0 +1,164 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use amd64::{
+    msr::{efer::ExtendedFeatureEnableReg, ModelSpecificReg},
+    paging::PageTableFlags,
+};
+
+use super::gdt::{GDTData, GDTReg};
+use crate::{
+    acpi::apic::{DeliveryMode, InterruptCommand},
+    timer::{SystemTimer, Timer},
+};
+
+mod trampoline;
+
+pub use trampoline::TRAMPOLINE_ADDR;
+
+/// Set by an AP once it is done initialising and about to enter the scheduler.
+static AP_READY: AtomicBool = AtomicBool::new(false);
+/// Timer the APs use to calibrate their LAPIC timer. Only valid while the BSP
+/// is inside [`start_aps`].
+static AP_TIMER: AtomicPtr<SystemTimer> = AtomicPtr::new(core::ptr::null_mut());
+
+extern "sysv64" fn ap_main(gdt: &'static mut GDTData) -> ! {
+    unsafe {
+        let gdtr = Box::leak(Box::new(GDTReg::new(gdt)));
+        gdtr.load();
+        crate::interrupts::idt::IDTR.load();
+    }
+
+    let state = unsafe { &*super::state::SYS_STATE.get() };
+    state.lapic.as_ref().unwrap().init_core(false);
+    let cpu = super::percpu::PerCpu::init();
+    super::tasking::userland::init_core();
+
+    let timer = unsafe { &*AP_TIMER.load(Ordering::Acquire) };
+    super::tasking::scheduler::Scheduler::add_core(gdt, timer);
+
+    debug!("Core {} is up", cpu.id);
+    AP_READY.store(true, Ordering::Release);
+    super::tasking::scheduler::Scheduler::unmask();
+
+    crate::hlt_loop!();
+}
+
+fn wake_ap(lapic: &crate::acpi::apic::LocalAPIC, apic_id: u8, timer: &SystemTimer) -> bool {
+    let vector = (trampoline::TRAMPOLINE_ADDR >> 12) as u8;
+
+    lapic.reset_error();
+    lapic.send_ipi(
+        InterruptCommand::new()
+            .with_delivery_mode(DeliveryMode::Init)
+            .with_assert(true)
+            .with_level_trigger(true)
+            .with_dest(apic_id),
+    );
+    timer.sleep(10);
+
+    for _ in 0..2 {
+        lapic.send_ipi(
+            InterruptCommand::new()
+                .with_vector(vector)
+                .with_delivery_mode(DeliveryMode::StartUp)
+                .with_assert(true)
+                .with_dest(apic_id),
+        );
+        timer.sleep(1);
+        if AP_READY.load(Ordering::Acquire) {
+            return true;
+        }
+    }
+
+    // A core that already started ignores the second SIPI, give it time to
+    // get through LAPIC timer calibration.
+    for _ in 0..1000 {
+        if AP_READY.load(Ordering::Acquire) {
+            return true;
+        }
+        timer.sleep(1);
+    }
+
+    false
+}
+
+pub fn start_aps(state: &crate::system::state::SystemState, timer: &SystemTimer) {
+    let lapic = state.lapic.as_ref().unwrap();
+    let bsp_id = lapic.id();
+    let apic_ids: Vec<_> = state
+        .madt
+        .as_ref()
+        .unwrap()
+        .lock()
+        .proc_lapics
+        .iter()
+        .filter(|v| {
+            let flags = v.flags;
+            flags.enabled() && v.apic_id != bsp_id
+        })
+        .map(|v| v.apic_id)
+        .collect();
+    if apic_ids.is_empty() {
+        debug!("No application processors to start");
+        return;
+    }
+
+    let (cr0, cr3, cr4): (u64, u64, u64);
+    unsafe {
+        core::arch::asm!(
+            "mov {}, cr0",
+            "mov {}, cr3",
+            "mov {}, cr4",
+            out(reg) cr0,
+            out(reg) cr3,
+            out(reg) cr4,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    // The trampoline loads CR3 while still in protected mode.
+    assert!(cr3 <= u64::from(u32::MAX), "Kernel PML4 is above 4 GiB");
+
+    let pml4 = state.pml4.as_ref().unwrap();
+    let data = unsafe {
+        pml4.lock().map(
+            trampoline::TRAMPOLINE_ADDR,
+            trampoline::TRAMPOLINE_ADDR,
+            1,
+            PageTableFlags::new_present().with_writable(true),
+        );
+        trampoline::install()
+    };
+    AP_TIMER.store(core::ptr::from_ref(timer).cast_mut(), Ordering::Release);
+
+    for apic_id in apic_ids {
+        let stack: &'static mut [u8] = vec![0; 0x4000].leak();
+        let gdt: &'static mut GDTData = Box::leak(Box::new(GDTData::new()));
+        AP_READY.store(false, Ordering::Release);
+        unsafe {
+            data.write(trampoline::TrampolineData {
+                cr0,
+                cr3,
+                // PCIDE can only be set once long mode is active
+                cr4: cr4 & !(1 << 17),
+                efer: u64::from(ExtendedFeatureEnableReg::read()),
+                stack: stack.as_ptr() as u64 + stack.len() as u64,
+                entry: ap_main as usize as u64,
+                arg: core::ptr::from_mut(gdt) as u64,
+            });
+        }
+        core::sync::atomic::fence(Ordering::SeqCst);
+
+        debug!("Starting core {apic_id}");
+        if !wake_ap(lapic, apic_id, timer) {
+            error!("Core {apic_id} did not respond, ignoring it");
+        }
+    }
+
+    AP_TIMER.store(core::ptr::null_mut(), Ordering::Release);
+    unsafe {
+        pml4.lock().unmap(trampoline::TRAMPOLINE_ADDR, 1);
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+/// Physical address the trampoline gets copied to. Must be page aligned and
+/// below 1 MiB, since the SIPI vector is the page number.
+pub const TRAMPOLINE_ADDR: u64 = 0x8000;
+
+/// Filled in by the BSP right before waking up an AP.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct TrampolineData {
+    pub cr0: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub efer: u64,
+    pub stack: u64,
+    pub entry: u64,
+    pub arg: u64,
+}
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+// Real mode -> protected mode -> long mode. Everything is addressed relative
+// to TRAMPOLINE_ADDR as this code runs from the copy, not from the kernel image.
+core::arch::global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_data",
+    ".global ap_trampoline_end",
+    ".set .Lap_gdtr, {base} + (.Lap_gdtr_rel - ap_trampoline_start)",
+    ".set .Lap_gdt, {base} + (.Lap_gdt_rel - ap_trampoline_start)",
+    ".set .Lap_prot, {base} + (.Lap_prot_rel - ap_trampoline_start)",
+    ".set .Lap_long, {base} + (.Lap_long_rel - ap_trampoline_start)",
+    ".set .Lap_data, {base} + (ap_trampoline_data - ap_trampoline_start)",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "xor ax, ax",
+    "mov ds, ax",
+    "lgdt [.Lap_gdtr]",
+    "mov eax, cr0",
+    "or eax, 1",
+    "mov cr0, eax",
+    // jmp dword 0x08:.Lap_prot
+    ".byte 0x66, 0xEA",
+    ".long .Lap_prot",
+    ".word 0x08",
+    ".code32",
+    ".Lap_prot_rel:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov eax, [.Lap_data + {cr4}]",
+    "mov cr4, eax",
+    "mov eax, [.Lap_data + {cr3}]",
+    "mov cr3, eax",
+    "mov ecx, 0xC0000080",
+    "mov eax, [.Lap_data + {efer}]",
+    "xor edx, edx",
+    "wrmsr",
+    "mov eax, [.Lap_data + {cr0}]",
+    "mov cr0, eax",
+    // jmp 0x18:.Lap_long
+    ".byte 0xEA",
+    ".long .Lap_long",
+    ".word 0x18",
+    ".code64",
+    ".Lap_long_rel:",
+    "mov rsp, [rip + ap_trampoline_data + {stack}]",
+    "mov rdi, [rip + ap_trampoline_data + {arg}]",
+    "mov rax, [rip + ap_trampoline_data + {entry}]",
+    "call rax",
+    "ud2",
+    ".balign 16",
+    ".Lap_gdt_rel:",
+    ".quad 0",
+    ".quad 0x00CF9A000000FFFF",
+    ".quad 0x00CF92000000FFFF",
+    ".quad 0x00AF9A000000FFFF",
+    ".Lap_gdtr_rel:",
+    ".word .Lap_gdtr_rel - .Lap_gdt_rel - 1",
+    ".long .Lap_gdt",
+    ".balign 8",
+    "ap_trampoline_data:",
+    ".fill {data_size}, 1, 0",
+    "ap_trampoline_end:",
+    ".popsection",
+    base = const TRAMPOLINE_ADDR,
+    cr0 = const core::mem::offset_of!(TrampolineData, cr0),
+    cr3 = const core::mem::offset_of!(TrampolineData, cr3),
+    cr4 = const core::mem::offset_of!(TrampolineData, cr4),
+    efer = const core::mem::offset_of!(TrampolineData, efer),
+    stack = const core::mem::offset_of!(TrampolineData, stack),
+    entry = const core::mem::offset_of!(TrampolineData, entry),
+    arg = const core::mem::offset_of!(TrampolineData, arg),
+    data_size = const core::mem::size_of::<TrampolineData>(),
+);
+
+/// Copies the trampoline to [`TRAMPOLINE_ADDR`] and returns where its data
+/// block ended up.
+pub unsafe fn install() -> *mut TrampolineData {
+    let start = core::ptr::addr_of!(ap_trampoline_start);
+    let len = core::ptr::addr_of!(ap_trampoline_end) as usize - start as usize;
+    let data_off = core::ptr::addr_of!(ap_trampoline_data) as usize - start as usize;
+    assert!(len <= amd64::paging::PAGE_SIZE as usize);
+
+    let dst = (TRAMPOLINE_ADDR + amd64::paging::PHYS_VIRT_OFFSET) as *mut u8;
+    core::ptr::copy_nonoverlapping(start, dst, len);
+    dst.add(data_off).cast()
+}
@@ -11,6 +11,7 @@ use super::{
 use crate::{
     acpi::{apic::LocalAPIC, madt::MADTData, ACPIState},
     incr_id::IncrementalIDGen,
+    timer::ClockSource,
 };
 
 pub static SYS_STATE: SyncUnsafeCell<SystemState> = SyncUnsafeCell::new(SystemState::new());
@@ -33,8 +34,10 @@ pub struct SystemState {
     pub acpi: Option<ACPIState>,
     pub madt: Option<spin::Mutex<MADTData>>,
     pub lapic: Option<LocalAPIC>,
+    pub clocksource: Option<Box<dyn ClockSource>>,
+    /// Unix time in nanoseconds at monotonic time zero.
+    pub wall_clock_offset: u64,
     pub scheduler: Option<spin::Mutex<Scheduler>>,
-    pub interrupt_context: Option<super::RegisterState>,
     pub in_panic: core::sync::atomic::AtomicBool,
     pub dt_index: Option<spin::RwLock<HashMap<u64, spin::Mutex<OSDTEntry>>>>,
     pub dt_id_gen: Option<spin::Mutex<IncrementalIDGen>>,
@@ -60,8 +63,9 @@ impl SystemState {
             acpi: None,
             madt: None,
             lapic: None,
+            clocksource: None,
+            wall_clock_offset: 0,
             scheduler: None,
-            interrupt_context: None,
             in_panic: core::sync::atomic::AtomicBool::new(false),
             dt_index: None,
             dt_id_gen: None,
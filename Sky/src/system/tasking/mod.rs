@@ -4,20 +4,66 @@ use alloc::{boxed::Box, collections::VecDeque, string::String, vec::Vec};
 
 use amd64::paging::PageTableFlags;
 use hashbrown::{HashMap, HashSet};
-use skykit::msg::Message;
+use skykit::{
+    msg::{Message, RecvFilter},
+    syscall::ThreadPriority,
+    ExitReason,
+};
 
 use super::gdt::{PrivilegeLevel, SegmentSelector};
 
 pub mod scheduler;
+pub mod timer_wheel;
 pub mod userland;
 
 pub const STACK_SIZE: u64 = 0x14000;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitReason {
+    Join(u64),
+    Sleep,
+    /// Physical address of the futex word.
+    Futex(u64),
+    /// Reply token of an outstanding `MsgCall`.
+    Reply(u64),
+    /// `LookupService` for a name nobody registered yet.
+    Service,
+    /// Notification ID with no bits set yet.
+    Notification(u64),
+    /// `MsgSend` or `MsgCall` to the PID with a full queue, see [`HeldSend`].
+    QueueSpace(u64),
+}
+
+/// A `MsgSend` or `MsgCall` held back by a full queue until the receiver
+/// takes a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeldSend {
+    pub tid: u64,
+    pub endpoint: u64,
+    pub addr: u64,
+    pub size: u64,
+    pub move_pages: bool,
+    /// Wait for the reply once sent.
+    pub call: bool,
+}
+
+/// A word of event bits other processes and bound IRQs can set, see
+/// [`scheduler::Scheduler::signal`]. Only threads of the owner wait on it.
+#[derive(Debug)]
+pub struct Notification {
+    pub owner: u64,
+    pub bits: u64,
+    /// Blocked threads, the oldest gets the next signal.
+    pub waiters: VecDeque<u64>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ThreadState {
     Active,
     Inactive,
+    /// Waiting for a message.
     Suspended,
+    Blocked(WaitReason),
 }
 
 impl ThreadState {
@@ -26,6 +72,11 @@ impl ThreadState {
         *self == Self::Suspended
     }
 
+    #[inline]
+    pub const fn is_blocked(&self) -> bool {
+        matches!(self, Self::Suspended | Self::Blocked(_))
+    }
+
     #[inline]
     pub fn is_inactive(&self) -> bool {
         *self == Self::Inactive
@@ -38,9 +89,18 @@ pub struct Thread {
     pub pid: u64,
     pub state: ThreadState,
     pub regs: super::RegisterState,
+    pub priority: ThreadPriority,
+    /// [`Self::priority`] plus aging and wake-up boosts, decides the run queue.
+    pub effective_priority: ThreadPriority,
+    /// TSC ticks left before the thread gets preempted.
+    pub time_slice: u64,
     pub fs_base: usize,
     pub gs_base: usize,
     pub stack_addr: u64,
+    /// Messages the thread takes while [`ThreadState::Suspended`].
+    pub recv_filter: RecvFilter,
+    /// Endpoint IDs the thread waits on, empty for all of them.
+    pub recv_endpoints: Vec<u64>,
 }
 
 impl Thread {
@@ -52,17 +112,29 @@ impl Thread {
             state: ThreadState::Inactive,
             regs: super::RegisterState {
                 rip,
-                cs: SegmentSelector::new(3, PrivilegeLevel::User).into(),
+                cs: SegmentSelector::new(4, PrivilegeLevel::User).into(),
                 rflags: 0x202,
                 rsp: stack_addr + STACK_SIZE,
-                ss: SegmentSelector::new(4, PrivilegeLevel::User).into(),
+                ss: SegmentSelector::new(3, PrivilegeLevel::User).into(),
                 ..Default::default()
             },
+            priority: ThreadPriority::Normal,
+            effective_priority: ThreadPriority::Normal,
+            time_slice: 0,
             fs_base: 0,
             gs_base: 0,
             stack_addr,
+            recv_filter: RecvFilter::Any,
+            recv_endpoints: Vec::new(),
         }
     }
+
+    /// Whether a message from `pid` to `endpoint` is one the thread waits for
+    /// in `MsgRecv`.
+    pub fn takes(&self, pid: u64, endpoint: u64) -> bool {
+        self.recv_filter.matches(pid)
+            && (self.recv_endpoints.is_empty() || self.recv_endpoints.contains(&endpoint))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -70,6 +142,42 @@ pub enum AllocationType {
     Kernel,
     Readable,
     Writable,
+    /// A mapping of a [`SharedRegion`], which owns the pages.
+    Shared {
+        region: u64,
+        writable: bool,
+    },
+}
+
+impl AllocationType {
+    #[inline]
+    pub const fn is_writable(self) -> bool {
+        matches!(self, Self::Writable | Self::Shared { writable: true, .. })
+    }
+}
+
+/// A named broadcast stream with a single publisher.
+#[derive(Debug)]
+pub struct Channel {
+    pub name: String,
+    pub publisher: u64,
+    /// Endpoints of the subscriptions, each gets its own copy of every
+    /// payload.
+    pub subscribers: Vec<u64>,
+}
+
+/// Pages any number of processes can map at once, always at the same
+/// address. Freed once nothing references them anymore.
+#[derive(Debug)]
+pub struct SharedRegion {
+    /// `None` once the creator is gone.
+    pub owner: Option<u64>,
+    pub addr: u64,
+    pub page_count: u64,
+    /// PIDs besides the owner that may map the region, and whether writable.
+    pub grants: HashMap<u64, bool>,
+    /// One for the owner while it lives, plus one per process mapping it.
+    pub refs: u64,
 }
 
 #[derive(Debug)]
@@ -79,10 +187,24 @@ pub struct Process {
     pub image_base: u64,
     pub cr3: spin::Mutex<Box<userland::page_table::UserPML4>>,
     pub messages: VecDeque<Message>,
+    /// Depth of [`Self::messages`] at which sends stop going through.
+    pub queue_limit: usize,
+    pub queue_high_water: usize,
+    /// Messages discarded because the queue was full.
+    pub queue_dropped: u64,
     pub allocations: HashMap<u64, (u64, AllocationType)>,
     pub msg_id_to_addr: HashMap<u64, u64>,
     pub addr_to_msg_id: HashMap<u64, u64>,
     pub thread_ids: HashSet<u64>,
+    /// Exit codes of threads nobody joined yet, their IDs stay reserved.
+    pub exited_threads: HashMap<u64, u64>,
+    /// Threads nobody will join, their IDs are freed as soon as they exit.
+    pub detached_threads: HashSet<u64>,
+    /// Cores that have the address space loaded, running one of its threads.
+    pub active_cores: HashSet<u8>,
+    /// Set once the process got torn down while some of its threads still ran
+    /// on other cores. It is dropped when the last of them is left.
+    pub dying: Option<ExitReason>,
     pub alloc_lock: spin::Mutex<()>,
 }
 
@@ -95,10 +217,17 @@ impl Process {
             image_base,
             cr3: Box::new(userland::page_table::UserPML4::new(id)).into(),
             messages: VecDeque::new(),
+            queue_limit: skykit::msg::DEFAULT_QUEUE_LIMIT as usize,
+            queue_high_water: 0,
+            queue_dropped: 0,
             allocations: HashMap::new(),
             msg_id_to_addr: HashMap::new(),
             addr_to_msg_id: HashMap::new(),
             thread_ids: HashSet::new(),
+            exited_threads: HashMap::new(),
+            detached_threads: HashSet::new(),
+            active_cores: HashSet::new(),
+            dying: None,
             alloc_lock: spin::Mutex::new(()),
         }
     }
@@ -110,6 +239,17 @@ impl Process {
         thread
     }
 
+    /// Queues a message nobody took right away. New messages go to the front.
+    pub fn push_message(&mut self, msg: Message) {
+        self.messages.push_front(msg);
+        self.queue_high_water = self.queue_high_water.max(self.messages.len());
+    }
+
+    #[inline]
+    pub fn queue_full(&self) -> bool {
+        self.messages.len() >= self.queue_limit
+    }
+
     pub fn track_alloc(&mut self, addr: u64, size: u64, ty: AllocationType) {
         let _lock = self.alloc_lock.lock();
 
@@ -152,7 +292,7 @@ impl Process {
                 addr - skykit::USER_VIRT_OFFSET,
                 page_count,
                 PageTableFlags::new_present()
-                    .with_writable(ty == AllocationType::Writable)
+                    .with_writable(ty.is_writable())
                     .with_user(true),
             );
         }
@@ -196,22 +336,48 @@ impl Process {
             "PID {}: Freeing {addr:#X} ({ty:?}, {page_count} pages, {size} bytes)",
             self.id
         );
+        drop(_lock);
 
-        unsafe {
-            (*crate::system::state::SYS_STATE.get())
-                .pmm
-                .as_ref()
-                .unwrap()
-                .lock()
-                .free((addr - skykit::USER_VIRT_OFFSET) as *mut _, page_count);
+        if ty != AllocationType::Kernel {
+            unsafe { self.unmap(addr, page_count) }
         }
 
-        if ty != AllocationType::Kernel {
-            drop(_lock);
-            unsafe { self.cr3.lock().unmap(addr, page_count) }
+        // The scheduler frees shared pages once the last reference is gone
+        if !matches!(ty, AllocationType::Shared { .. }) {
+            unsafe {
+                (*crate::system::state::SYS_STATE.get())
+                    .pmm
+                    .as_ref()
+                    .unwrap()
+                    .lock()
+                    .free((addr - skykit::USER_VIRT_OFFSET) as *mut _, page_count);
+            }
         }
     }
 
+    /// Forgets and unmaps the allocation at `addr` without freeing its pages,
+    /// for handing them to another process.
+    pub fn untrack_alloc(&mut self, addr: u64) -> (u64, AllocationType) {
+        let _lock = self.alloc_lock.lock();
+
+        let (size, ty) = self.allocations.remove(&addr).unwrap();
+        let page_count = size.div_ceil(0x1000);
+        trace!(
+            "PID {}: Giving away {addr:#X} ({ty:?}, {page_count} pages, {size} bytes)",
+            self.id
+        );
+        drop(_lock);
+        unsafe { self.unmap(addr, page_count) }
+        (size, ty)
+    }
+
+    /// Unmaps `count` pages at `addr` and flushes them out of the TLB of
+    /// every core running the process.
+    pub unsafe fn unmap(&self, addr: u64, count: u64) {
+        self.cr3.lock().unmap(addr, count);
+        crate::system::tlb::shootdown(self.active_cores.iter().copied());
+    }
+
     pub fn track_msg(&mut self, id: u64, addr: u64) {
         let _lock = self.alloc_lock.lock();
 
@@ -236,6 +402,14 @@ impl Process {
         self.free_alloc(addr);
     }
 
+    /// IDs of the [`SharedRegion`]s the process has mapped.
+    pub fn shared_regions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.allocations.values().filter_map(|(_, ty)| match ty {
+            AllocationType::Shared { region, .. } => Some(*region),
+            _ => None,
+        })
+    }
+
     pub fn is_msg(&self, addr: u64) -> bool {
         let _lock = self.alloc_lock.lock();
 
@@ -1,69 +1,136 @@
 // Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
 
-use alloc::{string::String, vec::Vec};
-use core::{cell::SyncUnsafeCell, ops::ControlFlow};
+use alloc::{boxed::Box, collections::VecDeque, string::String, vec::Vec};
+use core::ops::ControlFlow;
 
-use hashbrown::HashMap;
+use amd64::{
+    cpuid::CPUIdentification,
+    msr::{
+        seg_base::{FsBase, KernelGsBase},
+        ModelSpecificReg,
+    },
+};
+use hashbrown::{HashMap, HashSet};
 use skykit::{
-    msg::{KernelMessage, Message},
-    TerminationReason,
+    msg::{KernelMessage, Message, SendResult},
+    syscall::{FutexWaitResult, ThreadPriority},
+    ExitReason, TerminationReason,
 };
 
 use crate::{
+    acpi::apic::InterruptCommand,
     system::{
-        gdt::{PrivilegeLevel, SegmentSelector},
-        tasking::AllocationType,
+        gdt::{GDTData, GDTReg, PrivilegeLevel, SegmentSelector},
+        percpu::PerCpu,
+        tasking::{AllocationType, ThreadState, WaitReason},
         tss::TaskSegmentSelector,
         RegisterState,
     },
-    timer::Timer,
+    timer::SystemTimer,
 };
 
-static TSS: SyncUnsafeCell<TaskSegmentSelector> = SyncUnsafeCell::new(TaskSegmentSelector::new(0));
+/// In milliseconds.
+const TIME_SLICE: u64 = 10;
+/// Every this many milliseconds the head of each run queue moves up one level.
+const AGING_INTERVAL: u64 = 50;
+const PRIORITY_LEVELS: usize = ThreadPriority::Realtime as usize + 1;
+
+/// Where an IRQ goes when it fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IRQTarget {
+    /// A [`KernelMessage::IRQFired`] to a PID, acknowledged by `MsgAck`.
+    Message(u64),
+    /// A bit of a notification, acknowledged by taking the bit.
+    Notification { id: u64, bit: u8 },
+}
+
+/// The handlers sharing an IRQ line. Every one of them gets each IRQ, the
+/// line stays masked until all of them acknowledged it.
+#[derive(Debug, Default)]
+pub struct IRQLine {
+    /// The targets, and whether they still have to acknowledge the last IRQ.
+    pub handlers: Vec<(IRQTarget, bool)>,
+}
+
+impl IRQLine {
+    #[inline]
+    pub fn is_pending(&self) -> bool {
+        self.handlers.iter().any(|(_, pending)| *pending)
+    }
+}
 
 pub struct Scheduler {
     pub processes: HashMap<u64, super::Process>,
     pub threads: HashMap<u64, super::Thread>,
-    pub current_tid: Option<u64>,
-    pub current_pid: Option<u64>,
-    pub kern_stack: Vec<u8>,
-    pub irq_handlers: HashMap<u8, u64>,
-    pub message_sources: HashMap<u64, u64>,
+    /// Runnable thread IDs, one queue per [`ThreadPriority`].
+    pub run_queues: [VecDeque<u64>; PRIORITY_LEVELS],
+    pub timers: super::timer_wheel::TimerWheel,
+    /// Threads waiting on a futex, keyed on the physical address of its word.
+    pub futexes: HashMap<u64, VecDeque<u64>>,
+    pub last_aging: u64,
+    /// Cores sitting in the idle loop with nothing to run.
+    pub idle_cores: HashSet<u8>,
+    pub irq_handlers: HashMap<u8, IRQLine>,
+    /// Unacknowledged messages by ID, with the PID that frees the buffer, 0
+    /// for the kernel, and the receiving PID.
+    pub message_sources: HashMap<u64, (u64, u64)>,
+    /// Outstanding `MsgCall`s by reply token, the calling thread and the PID
+    /// allowed to reply.
+    pub pending_replies: HashMap<u64, (u64, u64)>,
+    /// Service names and the PIDs that registered them.
+    pub services: HashMap<String, u64>,
+    /// Sends waiting for space in a queue, by receiving PID, oldest first.
+    pub held_sends: HashMap<u64, VecDeque<super::HeldSend>>,
+    /// Threads blocked in `LookupService`, by the name they wait for.
+    pub service_waiters: HashMap<String, Vec<u64>>,
+    /// Owning PIDs of the endpoints made with `CreateEndpoint`. The default
+    /// endpoint, 0, of every process is not in here.
+    pub endpoints: HashMap<u64, u64>,
+    pub notifications: HashMap<u64, super::Notification>,
+    pub shared_regions: HashMap<u64, super::SharedRegion>,
+    pub channels: HashMap<u64, super::Channel>,
+    /// PIDs that get a [`KernelMessage::ProcessExited`] once the PID they
+    /// are filed under exits.
+    pub monitors: HashMap<u64, HashSet<u64>>,
     pub pid_gen: crate::incr_id::IncrementalIDGen,
     pub tid_gen: crate::incr_id::IncrementalIDGen,
     pub msg_id_gen: crate::incr_id::IncrementalIDGen,
+    /// Last reply token handed out. Tokens never repeat, so one that was
+    /// used up can't answer a later call.
+    pub last_reply_token: u64,
+    pub endpoint_gen: crate::incr_id::IncrementalIDGen,
+    pub notification_gen: crate::incr_id::IncrementalIDGen,
+    pub shared_region_gen: crate::incr_id::IncrementalIDGen,
+    pub channel_gen: crate::incr_id::IncrementalIDGen,
 }
 
 unsafe extern "sysv64" fn irq_handler(state: &mut RegisterState) {
     let irq = (state.int_num - 0x20) as u8;
     crate::acpi::ioapic::set_irq_mask(irq, true);
-    let mut this = (*crate::system::state::SYS_STATE.get())
-        .scheduler
-        .as_ref()
-        .unwrap()
-        .lock();
-    let pid = this.irq_handlers.get(&irq).copied().unwrap();
-    let s: &mut [u8] = postcard::to_allocvec(&KernelMessage::IRQFired(irq))
-        .unwrap()
-        .leak();
-
-    let virt = this
-        .processes
-        .get_mut(&pid)
-        .unwrap()
-        .track_kernelside_alloc(s.as_ptr() as _, s.len() as _);
-
-    let msg = Message::new(
-        this.msg_id_gen.next(),
-        0,
-        core::slice::from_raw_parts(virt as *const _, s.len() as _),
-    );
-    this.message_sources.insert(msg.id, 0);
-    let process = this.processes.get_mut(&pid).unwrap();
-    process.track_msg(msg.id, virt);
-
-    let tids = process.thread_ids.clone();
-    if super::userland::handlers::msg::handle_new(&mut this, pid, tids, msg).is_break() {
+    let mut this = lock();
+    let Some(line) = this.irq_handlers.get_mut(&irq) else {
+        return;
+    };
+    // All pending before any gets delivered, signalling may acknowledge
+    // right away
+    for (_, pending) in &mut line.handlers {
+        *pending = true;
+    }
+    let targets: Vec<_> = line.handlers.iter().map(|(v, _)| *v).collect();
+
+    let idle = this.current_tid().is_none();
+    let mut resched = false;
+    for target in targets {
+        resched |= match target {
+            IRQTarget::Message(pid) => this
+                .send_kernel_msg(pid, &KernelMessage::IRQFired(irq), true)
+                .is_break(),
+            IRQTarget::Notification { id, bit } => this
+                .signal(id, 1 << bit)
+                .is_some_and(|tid| this.boost(tid) || idle),
+        };
+    }
+    if resched {
         this.schedule(state);
     }
 }
@@ -73,40 +140,26 @@ extern "C" fn idle() {
 }
 
 pub unsafe extern "sysv64" fn schedule(state: &mut RegisterState) {
-    (*crate::system::state::SYS_STATE.get())
-        .scheduler
-        .as_ref()
-        .unwrap()
-        .lock()
-        .schedule(state);
+    lock().tick(state);
+}
+
+/// Takes the scheduler lock. Its holder may wait on TLB shootdowns, which
+/// every core spinning here serves, interrupts being off.
+pub fn lock() -> spin::MutexGuard<'static, Scheduler> {
+    let state = unsafe { &*crate::system::state::SYS_STATE.get() };
+    let scheduler = state.scheduler.as_ref().unwrap();
+    loop {
+        if let Some(v) = scheduler.try_lock() {
+            return v;
+        }
+        crate::system::tlb::serve();
+        core::hint::spin_loop();
+    }
 }
 
 impl Scheduler {
     #[inline]
-    pub fn new(timer: &impl Timer) -> Self {
-        let kern_stack = vec![0; 0x14000];
-
-        unsafe {
-            let gdt = &mut *crate::system::gdt::GDT.get();
-            (*TSS.get()) =
-                TaskSegmentSelector::new(kern_stack.as_ptr() as u64 + kern_stack.len() as u64);
-            let tss_addr = TSS.get() as u64;
-            gdt.task_segment.base_low = tss_addr as u16;
-            gdt.task_segment.base_middle = (tss_addr >> 16) as u8;
-            gdt.task_segment.attrs = gdt.task_segment.attrs.with_present(true);
-            gdt.task_segment.base_high = (tss_addr >> 24) as u8;
-            gdt.task_segment.base_upper = (tss_addr >> 32) as u32;
-
-            core::arch::asm!(
-                "ltr ax",
-                in("ax") SegmentSelector::new(5, crate::system::gdt::PrivilegeLevel::Supervisor).0,
-                options(nostack, preserves_flags),
-            );
-        }
-
-        let state = unsafe { &mut *crate::system::state::SYS_STATE.get() };
-        state.lapic.as_ref().unwrap().setup_timer(timer);
-
+    pub fn new(timer: &SystemTimer) -> Self {
         crate::interrupts::idt::set_handler(
             128,
             1,
@@ -116,25 +169,83 @@ impl Scheduler {
             true,
         );
         crate::acpi::ioapic::wire_legacy_irq(96, false);
+        crate::system::tlb::init();
 
+        Self::add_core(unsafe { &mut *crate::system::gdt::GDT.get() }, timer);
         Self {
             processes: HashMap::new(),
             threads: HashMap::new(),
-            current_tid: None,
-            current_pid: None,
-            kern_stack,
+            run_queues: Default::default(),
+            timers: super::timer_wheel::TimerWheel::new(),
+            futexes: HashMap::new(),
+            last_aging: 0,
+            idle_cores: HashSet::new(),
             irq_handlers: HashMap::new(),
             message_sources: HashMap::new(),
+            monitors: HashMap::new(),
+            pending_replies: HashMap::new(),
+            services: HashMap::new(),
+            held_sends: HashMap::new(),
+            service_waiters: HashMap::new(),
+            endpoints: HashMap::new(),
+            notifications: HashMap::new(),
+            shared_regions: HashMap::new(),
+            channels: HashMap::new(),
             pid_gen: crate::incr_id::IncrementalIDGen::new(),
             tid_gen: crate::incr_id::IncrementalIDGen::new(),
             msg_id_gen: crate::incr_id::IncrementalIDGen::new(),
+            last_reply_token: 0,
+            endpoint_gen: crate::incr_id::IncrementalIDGen::new(),
+            notification_gen: crate::incr_id::IncrementalIDGen::new(),
+            shared_region_gen: crate::incr_id::IncrementalIDGen::new(),
+            channel_gen: crate::incr_id::IncrementalIDGen::new(),
+        }
+    }
+
+    /// Gives the calling core its own kernel stack and TSS, then calibrates
+    /// its LAPIC timer. The timer stays masked until [`Self::unmask`] and only
+    /// ever gets armed for the next deadline, there is no periodic tick.
+    ///
+    /// Cores whose LAPIC timer stops in deep C-states, which `hlt` may enter,
+    /// get an HPET comparator for their deadlines instead, while there are
+    /// some left.
+    pub fn add_core(gdt: &mut GDTData, timer: &SystemTimer) {
+        let cpu = PerCpu::current();
+        cpu.kern_stack = vec![0; super::STACK_SIZE as usize];
+        cpu.kern_rsp = cpu.kern_stack.as_ptr() as u64 + cpu.kern_stack.len() as u64;
+        let tss = Box::leak(Box::new(TaskSegmentSelector::new(cpu.kern_rsp)));
+        gdt.set_tss(tss);
+        unsafe { GDTReg::load_tss() }
+        cpu.tss = Some(tss);
+
+        cpu.timer_cal = cpu.lapic.setup_timer(timer);
+        if !CPUIdentification::new().always_running_apic_timer {
+            if let SystemTimer::Hpet(hpet) = timer {
+                cpu.clock_event = hpet.alloc_comparator(128, cpu.id, false);
+            }
+            if cpu.clock_event.is_none() {
+                warn!("Core {} has no timer that survives deep C-states", cpu.id);
+            }
         }
+        trace!("Core {} joined the scheduler", cpu.id);
+    }
+
+    /// Milliseconds of monotonic time.
+    pub fn now(&self) -> u64 {
+        crate::timer::monotonic_ns() / 1_000_000
+    }
+
+    pub fn current_tid(&self) -> Option<u64> {
+        PerCpu::current().current_tid
+    }
+
+    pub fn current_pid(&self) -> Option<u64> {
+        PerCpu::current().current_pid
     }
 
     pub fn unmask() {
         crate::sti!();
-        let state = unsafe { &*crate::system::state::SYS_STATE.get() };
-        let lapic = state.lapic.as_ref().unwrap();
+        let lapic = PerCpu::current().lapic;
         lapic.write_timer(lapic.read_timer().with_mask(false));
         unsafe { core::arch::asm!("int 128", options(nostack, preserves_flags)) }
     }
@@ -193,68 +304,608 @@ impl Scheduler {
         let tid = self.tid_gen.next();
         let stack_addr = proc.allocate(super::STACK_SIZE).0;
         let thread = proc.new_thread(tid, virt_addr + exec.ehdr.e_entry, stack_addr);
-        self.threads.try_insert(tid, thread).unwrap()
+        self.threads.try_insert(tid, thread).unwrap();
+        self.wake(tid);
+        self.threads.get_mut(&tid).unwrap()
     }
 
     pub fn current_thread_mut(&mut self) -> Option<&mut super::Thread> {
-        self.threads.get_mut(&self.current_tid?)
+        let tid = self.current_tid()?;
+        self.threads.get_mut(&tid)
     }
 
     pub fn current_process(&self) -> Option<&super::Process> {
-        self.processes.get(&self.current_pid?)
+        self.processes.get(&self.current_pid()?)
     }
 
     pub fn current_process_mut(&mut self) -> Option<&mut super::Process> {
-        self.processes.get_mut(&self.current_pid?)
+        let pid = self.current_pid()?;
+        self.processes.get_mut(&pid)
+    }
+
+    /// Whether another core tore down the current process, the current
+    /// thread only has to go then.
+    pub fn current_dying(&self) -> bool {
+        self.current_process().is_some_and(|v| v.dying.is_some())
+    }
+
+    /// Puts an inactive thread at the back of its run queue.
+    pub fn enqueue(&mut self, tid: u64) {
+        let thread = &self.threads[&tid];
+        debug_assert!(thread.state.is_inactive());
+        self.run_queues[thread.effective_priority as usize].push_back(tid);
+    }
+
+    /// Like [`Self::enqueue`], but also gets an idle core to pick it up.
+    pub fn wake(&mut self, tid: u64) {
+        self.enqueue(tid);
+
+        let cpu = PerCpu::current();
+        let Some(&core) = self.idle_cores.iter().find(|&&v| v != cpu.id) else {
+            return;
+        };
+        self.idle_cores.remove(&core);
+        cpu.lapic
+            .send_ipi(InterruptCommand::new().with_vector(128).with_dest(core));
+    }
+
+    fn dequeue(&mut self, tid: u64) {
+        for queue in &mut self.run_queues {
+            queue.retain(|v| *v != tid);
+        }
+        self.timers.cancel(tid);
+        match self.threads.get(&tid).map(|v| &v.state) {
+            Some(ThreadState::Blocked(WaitReason::Futex(key))) => {
+                self.remove_futex_waiter(*key, tid);
+            }
+            Some(ThreadState::Blocked(WaitReason::Reply(token))) => {
+                let token = *token;
+                self.pending_replies.remove(&token);
+            }
+            Some(ThreadState::Blocked(WaitReason::QueueSpace(pid))) => {
+                let pid = *pid;
+                if let Some(queue) = self.held_sends.get_mut(&pid) {
+                    queue.retain(|v| v.tid != tid);
+                    if queue.is_empty() {
+                        self.held_sends.remove(&pid);
+                    }
+                }
+            }
+            Some(ThreadState::Blocked(WaitReason::Notification(id))) => {
+                if let Some(notification) = self.notifications.get_mut(id) {
+                    notification.waiters.retain(|v| *v != tid);
+                }
+            }
+            Some(ThreadState::Blocked(WaitReason::Service)) => {
+                self.service_waiters.retain(|_, v| {
+                    v.retain(|v| *v != tid);
+                    !v.is_empty()
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Releases what a process that just got removed held onto outside of
+    /// its own structures, then drops it.
+    fn process_exited(&mut self, mut proc: super::Process, reason: ExitReason) {
+        let pid = proc.id;
+        debug!("PID {pid} exited: {reason:?}");
+        self.settle_messages(&mut proc);
+        self.release_irqs(|_, v| *v == IRQTarget::Message(pid));
+        let parent = crate::system::fkext::process_exited(pid);
+        let mapped: Vec<_> = proc.shared_regions().collect();
+        drop(proc);
+        for id in mapped {
+            self.release_shared(id);
+        }
+        let owned: Vec<_> = self
+            .shared_regions
+            .iter_mut()
+            .filter_map(|(id, v)| {
+                v.grants.remove(&pid);
+                (v.owner == Some(pid)).then_some(*id)
+            })
+            .collect();
+        for id in owned {
+            self.shared_regions.get_mut(&id).unwrap().owner = None;
+            self.release_shared(id);
+        }
+
+        self.abandon_calls(pid);
+        for held in self.held_sends.remove(&pid).unwrap_or_default() {
+            if held.call {
+                self.abandon_call(held.tid, pid);
+                continue;
+            }
+            let thread = self.threads.get_mut(&held.tid).unwrap();
+            super::userland::handlers::msg::set_send_result(&mut thread.regs, SendResult::Dropped);
+            thread.state = ThreadState::Inactive;
+            self.wake(held.tid);
+        }
+        self.services.retain(|name, v| {
+            if *v == pid {
+                debug!("Service {name} is gone with PID {pid}");
+            }
+            *v != pid
+        });
+        let endpoints: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|(_, owner)| **owner == pid)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in endpoints {
+            self.remove_endpoint(id);
+        }
+        self.channels.retain(|_, v| {
+            if v.publisher == pid {
+                debug!("Channel {} is gone with PID {pid}", v.name);
+            }
+            v.publisher != pid
+        });
+        let notifications: Vec<_> = self
+            .notifications
+            .iter()
+            .filter(|(_, v)| v.owner == pid)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in notifications {
+            self.remove_notification(id);
+        }
+
+        let mut watchers = self.monitors.remove(&pid).unwrap_or_default();
+        self.monitors.retain(|_, v| {
+            v.remove(&pid);
+            !v.is_empty()
+        });
+        watchers.extend(parent);
+        for target in watchers {
+            if self.processes.contains_key(&target) {
+                let msg = KernelMessage::ProcessExited { pid, reason };
+                let _ = self.send_kernel_msg(target, &msg, false);
+            }
+        }
+    }
+
+    /// Queues `msg` for `pid` as a message from the kernel, or hands it to a
+    /// thread waiting for one. Breaks if this core should reschedule.
+    pub fn send_kernel_msg(
+        &mut self,
+        pid: u64,
+        msg: &KernelMessage,
+        boost: bool,
+    ) -> ControlFlow<Option<TerminationReason>> {
+        let s: &mut [u8] = postcard::to_allocvec(msg).unwrap().leak();
+
+        let virt = self
+            .processes
+            .get_mut(&pid)
+            .unwrap()
+            .track_kernelside_alloc(s.as_ptr() as _, s.len() as _);
+
+        let msg = Message::new(self.msg_id_gen.next(), 0, unsafe {
+            core::slice::from_raw_parts(virt as *const _, s.len() as _)
+        });
+        self.message_sources.insert(msg.id, (0, pid));
+        let process = self.processes.get_mut(&pid).unwrap();
+        process.track_msg(msg.id, virt);
+
+        let tids = process.thread_ids.clone();
+        super::userland::handlers::msg::handle_new(self, pid, tids, msg, boost)
+    }
+
+    /// Settles the messages `proc` sent or got that were not acknowledged
+    /// yet. Buffers it sent become the receivers' to free, as if they were
+    /// moved, the ones it got go back to their senders.
+    fn settle_messages(&mut self, proc: &mut super::Process) {
+        let pid = proc.id;
+        let ids: Vec<_> = self
+            .message_sources
+            .iter()
+            .filter(|(_, (owner, receiver))| *owner == pid || *receiver == pid)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            let (owner, receiver) = self.message_sources.remove(&id).unwrap();
+            if receiver == pid {
+                // Buffers of the process itself or the kernel go with it
+                if owner != pid && owner != 0 {
+                    self.processes.get_mut(&owner).unwrap().free_msg(id);
+                }
+                self.msg_id_gen.free(id);
+                continue;
+            }
+
+            let addr = proc.msg_id_to_addr.remove(&id).unwrap();
+            proc.addr_to_msg_id.remove(&addr);
+            let (size, _) = proc.untrack_alloc(addr);
+            let target = self.processes.get_mut(&receiver).unwrap();
+            target.track_alloc(addr, size, AllocationType::Readable);
+            target.track_msg(id, addr);
+            self.message_sources.insert(id, (receiver, receiver));
+        }
+    }
+
+    /// Frees endpoint `id`, ending the channel subscription it may be for.
+    pub fn remove_endpoint(&mut self, id: u64) {
+        self.endpoints.remove(&id);
+        self.endpoint_gen.free(id);
+        for channel in self.channels.values_mut() {
+            channel.subscribers.retain(|v| *v != id);
+        }
+    }
+
+    /// Drops a reference to shared region `id`, freeing its pages if it was
+    /// the last one.
+    pub fn release_shared(&mut self, id: u64) {
+        let region = self.shared_regions.get_mut(&id).unwrap();
+        region.refs -= 1;
+        if region.refs != 0 {
+            return;
+        }
+        let region = self.shared_regions.remove(&id).unwrap();
+        trace!(
+            "Freeing shared region {id} ({} pages at {:#X})",
+            region.page_count,
+            region.addr
+        );
+        unsafe {
+            (*crate::system::state::SYS_STATE.get())
+                .pmm
+                .as_ref()
+                .unwrap()
+                .lock()
+                .free(
+                    (region.addr - skykit::USER_VIRT_OFFSET) as *mut _,
+                    region.page_count,
+                );
+        }
+        self.shared_region_gen.free(id);
+    }
+
+    /// ORs `bits` into notification `id`. If a thread waits on it, it takes
+    /// the whole word and gets woken. Returns that thread.
+    pub fn signal(&mut self, id: u64, bits: u64) -> Option<u64> {
+        let notification = self.notifications.get_mut(&id)?;
+        notification.bits |= bits;
+        if notification.bits == 0 {
+            return None;
+        }
+        let tid = notification.waiters.pop_front()?;
+        let bits = core::mem::take(&mut notification.bits);
+        self.ack_irqs(id, bits);
+        let thread = self.threads.get_mut(&tid).unwrap();
+        thread.regs.rax = bits;
+        thread.state = ThreadState::Inactive;
+        self.timers.cancel(tid);
+        self.wake(tid);
+        Some(tid)
+    }
+
+    /// Acknowledges the IRQs bound to the `bits` of notification `id` that
+    /// were just taken.
+    pub fn ack_irqs(&mut self, id: u64, bits: u64) {
+        let irqs: Vec<_> = self
+            .irq_handlers
+            .iter()
+            .flat_map(|(irq, line)| line.handlers.iter().map(move |(v, _)| (*irq, *v)))
+            .filter(|(_, v)| {
+                matches!(*v, IRQTarget::Notification { id: v, bit } if v == id && bits & (1 << bit) != 0)
+            })
+            .collect();
+        for (irq, target) in irqs {
+            self.ack_irq(irq, target);
+        }
+    }
+
+    /// Marks IRQ `irq` as handled by `target`, unmasking the line once no
+    /// other handler is left to do so.
+    pub fn ack_irq(&mut self, irq: u8, target: IRQTarget) {
+        let Some(line) = self.irq_handlers.get_mut(&irq) else {
+            return;
+        };
+        let Some((_, pending)) = line
+            .handlers
+            .iter_mut()
+            .find(|(v, pending)| *v == target && *pending)
+        else {
+            return;
+        };
+        *pending = false;
+        if !line.is_pending() {
+            crate::acpi::ioapic::set_irq_mask(irq, false);
+        }
+    }
+
+    /// Drops the IRQ handlers `f` picks. Lines left without any stay masked,
+    /// the rest no longer wait for the dropped ones to acknowledge.
+    pub fn release_irqs(&mut self, f: impl Fn(u8, &IRQTarget) -> bool) -> usize {
+        let mut released = 0;
+        self.irq_handlers.retain(|irq, line| {
+            let was_pending = line.is_pending();
+            let count = line.handlers.len();
+            line.handlers.retain(|(v, _)| !f(*irq, v));
+            if line.handlers.len() == count {
+                return true;
+            }
+            released += count - line.handlers.len();
+            if line.handlers.is_empty() {
+                debug!("IRQ {irq} has no handlers left");
+                crate::acpi::ioapic::set_irq_mask(*irq, true);
+                return false;
+            }
+            if was_pending && !line.is_pending() {
+                crate::acpi::ioapic::set_irq_mask(*irq, false);
+            }
+            true
+        });
+        released
+    }
+
+    /// Drops notification `id` and its IRQ bindings. Threads still waiting
+    /// get no bits.
+    pub fn remove_notification(&mut self, id: u64) {
+        let Some(notification) = self.notifications.remove(&id) else {
+            return;
+        };
+        self.notification_gen.free(id);
+        self.release_irqs(|_, v| matches!(*v, IRQTarget::Notification { id: v, .. } if v == id));
+        for tid in notification.waiters {
+            let thread = self.threads.get_mut(&tid).unwrap();
+            thread.regs.rax = 0;
+            thread.state = ThreadState::Inactive;
+            self.timers.cancel(tid);
+            self.wake(tid);
+        }
+    }
+
+    /// Wakes everyone waiting on a `MsgCall` to `pid` with an empty reply.
+    fn abandon_calls(&mut self, pid: u64) {
+        let tokens: Vec<_> = self
+            .pending_replies
+            .iter()
+            .filter(|(_, (_, callee))| *callee == pid)
+            .map(|(token, _)| *token)
+            .collect();
+        for token in tokens {
+            let (tid, _) = self.pending_replies.remove(&token).unwrap();
+            self.abandon_call(tid, pid);
+        }
+    }
+
+    /// Wakes `tid` from a `MsgCall` to `pid` with an empty reply.
+    fn abandon_call(&mut self, tid: u64, pid: u64) {
+        let thread = self.threads.get_mut(&tid).unwrap();
+        thread.regs.rax = 0;
+        thread.regs.rdi = pid;
+        thread.regs.rsi = 0;
+        thread.regs.rdx = 0;
+        if super::userland::is_syscall(&thread.regs) {
+            thread.regs.r8 = 0;
+            thread.regs.r9 = 0;
+        }
+        thread.state = ThreadState::Inactive;
+        self.wake(tid);
+    }
+
+    pub fn remove_futex_waiter(&mut self, key: u64, tid: u64) {
+        let Some(queue) = self.futexes.get_mut(&key) else {
+            return;
+        };
+        queue.retain(|v| *v != tid);
+        if queue.is_empty() {
+            self.futexes.remove(&key);
+        }
+    }
+
+    fn highest_ready(&self) -> Option<ThreadPriority> {
+        self.run_queues
+            .iter()
+            .rposition(|v| !v.is_empty())
+            .map(|v| ThreadPriority::try_from(v as u64).unwrap())
+    }
+
+    fn next_thread(&mut self) -> Option<u64> {
+        for queue in self.run_queues.iter_mut().rev() {
+            while let Some(tid) = queue.pop_front() {
+                if self
+                    .threads
+                    .get(&tid)
+                    .is_some_and(|v| v.state.is_inactive())
+                {
+                    return Some(tid);
+                }
+            }
+        }
+        None
+    }
+
+    /// Moves the longest waiting thread of every level below the top one up
+    /// a level, so low priority threads cannot starve.
+    fn age(&mut self) {
+        for level in (0..PRIORITY_LEVELS - 1).rev() {
+            let Some(tid) = self.run_queues[level].pop_front() else {
+                continue;
+            };
+            let Some(thread) = self.threads.get_mut(&tid) else {
+                continue;
+            };
+            if !thread.state.is_inactive() {
+                continue;
+            }
+            thread.effective_priority = ThreadPriority::try_from(level as u64 + 1).unwrap();
+            self.run_queues[level + 1].push_back(tid);
+        }
+    }
+
+    /// Gives `tid` the highest priority until its next time slice runs out.
+    /// Returns whether it should preempt the thread running on this core.
+    pub fn boost(&mut self, tid: u64) -> bool {
+        let thread = self.threads.get_mut(&tid).unwrap();
+        let old = thread.effective_priority;
+        thread.effective_priority = ThreadPriority::Realtime;
+        if thread.state.is_inactive() && old != ThreadPriority::Realtime {
+            let queue = &mut self.run_queues[old as usize];
+            if let Some(i) = queue.iter().position(|v| *v == tid) {
+                queue.remove(i);
+                self.run_queues[ThreadPriority::Realtime as usize].push_back(tid);
+            }
+        }
+
+        self.current_thread_mut()
+            .is_none_or(|v| v.effective_priority < ThreadPriority::Realtime)
     }
 
-    pub fn next_thread_mut(&mut self) -> Option<&mut super::Thread> {
-        let mut i = self.current_tid.map(|v| v + 1).unwrap_or_default() as usize;
-        if i > self.threads.len() {
-            i = 0;
+    /// Timer interrupt, only switches threads once the time slice is used
+    /// up or a higher priority thread became ready.
+    pub unsafe fn tick(&mut self, state: &mut RegisterState) {
+        let now = self.now();
+        for tid in self.timers.advance_to(now) {
+            let Some(thread) = self.threads.get_mut(&tid) else {
+                continue;
+            };
+            match thread.state {
+                ThreadState::Blocked(WaitReason::Sleep) => {}
+                ThreadState::Blocked(WaitReason::Futex(key)) => {
+                    thread.regs.rax = FutexWaitResult::TimedOut as u64;
+                    self.remove_futex_waiter(key, tid);
+                }
+                ThreadState::Blocked(WaitReason::Notification(id)) => {
+                    if let Some(notification) = self.notifications.get_mut(&id) {
+                        notification.waiters.retain(|v| *v != tid);
+                    }
+                }
+                // Receive timed out, return no message
+                ThreadState::Suspended => {
+                    thread.regs.rax = 0;
+                    thread.regs.rdi = 0;
+                    thread.regs.rsi = 0;
+                    thread.regs.rdx = 0;
+                    if super::userland::is_syscall(&thread.regs) {
+                        thread.regs.r8 = 0;
+                        thread.regs.r9 = 0;
+                    }
+                }
+                _ => continue,
+            }
+            self.threads.get_mut(&tid).unwrap().state = ThreadState::Inactive;
+            self.wake(tid);
+        }
+        if now - self.last_aging >= AGING_INTERVAL {
+            self.last_aging = now;
+            self.age();
+        }
+
+        let highest = self.highest_ready();
+        let slice_left =
+            PerCpu::current().slice_end > core::arch::x86_64::_rdtsc() && !self.current_dying();
+        if let Some(thread) = self.current_thread_mut() {
+            if slice_left && highest <= Some(thread.effective_priority) {
+                self.arm_timer();
+                return;
+            }
         }
 
-        self.threads
-            .values_mut()
-            .skip(i)
-            .find(|v| v.state.is_inactive())
+        self.schedule(state);
+    }
+
+    /// Arms the LAPIC timer of this core for the end of the running thread's
+    /// time slice or the next sleeper, whichever comes first. Idle cores
+    /// without either get no timer interrupts at all, unless the clock source
+    /// has to be read before it wraps.
+    fn arm_timer(&self) {
+        let cpu = PerCpu::current();
+        let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+        let ns_to_tsc = |ns: u64| {
+            tsc + (u128::from(ns) * u128::from(cpu.timer_cal.tsc_ticks_per_ms) / 1_000_000) as u64
+        };
+        let now = crate::timer::monotonic_ns();
+        let sleeper = self
+            .timers
+            .next_deadline()
+            .map(|v| ns_to_tsc(v.saturating_mul(1_000_000).saturating_sub(now)));
+        let slice_end = cpu.current_tid.map(|_| cpu.slice_end);
+        let wrap = crate::timer::max_idle_ns().map(ns_to_tsc);
+        let deadline = sleeper.into_iter().chain(slice_end).chain(wrap).min();
+        let Some(clock_event) = &cpu.clock_event else {
+            cpu.lapic.arm_timer(&cpu.timer_cal, deadline);
+            return;
+        };
+        match deadline {
+            Some(v) => clock_event.one_shot(
+                (u128::from(v.saturating_sub(tsc)) * 1_000_000
+                    / u128::from(cpu.timer_cal.tsc_ticks_per_ms)) as u64,
+            ),
+            None => clock_event.stop(),
+        }
     }
 
     pub unsafe fn schedule(&mut self, state: &mut RegisterState) {
+        if self.current_dying() {
+            self.leave_dying();
+        }
+        let tsc = core::arch::x86_64::_rdtsc();
+        let slice_end = PerCpu::current().slice_end;
         if let Some(old_thread) = self.current_thread_mut() {
             old_thread.regs = *state;
-            if !old_thread.state.is_suspended() {
-                old_thread.state = super::ThreadState::Inactive;
+            old_thread.fs_base = FsBase::read().base() as usize;
+            old_thread.gs_base = KernelGsBase::read().base() as usize;
+            old_thread.time_slice = slice_end.saturating_sub(tsc);
+            // Boosts and aging only last until the thread blocks or uses up
+            // its time slice
+            if old_thread.state.is_blocked() || old_thread.time_slice == 0 {
+                old_thread.effective_priority = old_thread.priority;
+            }
+            if !old_thread.state.is_blocked() {
+                old_thread.state = ThreadState::Inactive;
+                let tid = old_thread.id;
+                self.enqueue(tid);
             }
         }
+        let cpu = PerCpu::current();
+        if let Some(proc) = cpu.current_pid.and_then(|v| self.processes.get_mut(&v)) {
+            proc.active_cores.remove(&cpu.id);
+        }
 
-        let Some(thread) = self.next_thread_mut() else {
+        let Some(tid) = self.next_thread() else {
             *state = RegisterState {
                 rip: idle as usize as _,
                 cs: SegmentSelector::new(1, PrivilegeLevel::Supervisor).into(),
                 rflags: 0x202,
-                rsp: self.kern_stack.as_ptr() as u64 + self.kern_stack.len() as u64,
+                rsp: cpu.kern_rsp,
                 ss: SegmentSelector::new(2, PrivilegeLevel::Supervisor).into(),
                 ..Default::default()
             };
-            (*crate::system::state::SYS_STATE.get())
-                .pml4
-                .as_ref()
-                .unwrap()
-                .lock()
-                .set_cr3();
-            self.current_tid = None;
-            self.current_pid = None;
+            cpu.current_tid = None;
+            cpu.current_pid = None;
+            self.idle_cores.insert(cpu.id);
+            Self::load_kernel_pml4();
+            self.arm_timer();
             return;
         };
 
+        let tsc_ticks_per_ms = PerCpu::current().timer_cal.tsc_ticks_per_ms;
+        let thread = self.threads.get_mut(&tid).unwrap();
         *state = thread.regs;
-        thread.state = super::ThreadState::Active;
+        thread.state = ThreadState::Active;
+        if thread.time_slice == 0 {
+            thread.time_slice = TIME_SLICE * tsc_ticks_per_ms;
+        }
+        // The user GS base goes live on the `swapgs` before `iretq`
+        FsBase::new().with_base(thread.fs_base as u64).write();
+        KernelGsBase::new().with_base(thread.gs_base as u64).write();
         let pid = thread.pid;
         let tid = Some(thread.id);
-        self.processes.get_mut(&pid).unwrap().cr3.lock().set_cr3();
-        self.current_tid = tid;
-        self.current_pid = Some(pid);
+        let slice_end = tsc + thread.time_slice;
+        let proc = self.processes.get_mut(&pid).unwrap();
+        proc.cr3.lock().set_cr3();
+        proc.active_cores.insert(cpu.id);
+        cpu.current_tid = tid;
+        cpu.current_pid = Some(pid);
+        cpu.slice_end = slice_end;
+        self.idle_cores.remove(&cpu.id);
+        self.arm_timer();
     }
 
     pub fn register_irq(
@@ -265,12 +916,89 @@ impl Scheduler {
         if irq > 0xDF {
             return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
         }
-        let pid = self.current_pid.unwrap();
-        if self.irq_handlers.try_insert(irq, pid).is_err() {
+        let pid = self.current_pid().unwrap();
+        self.add_irq_handler(irq, IRQTarget::Message(pid))
+    }
+
+    /// Routes IRQ `rsi` to bit `r10` of notification `rdx`, which the current
+    /// process must own.
+    pub fn bind_irq(&mut self, state: &RegisterState) -> ControlFlow<Option<TerminationReason>> {
+        let (irq, id, bit) = (state.rsi, state.rdx, state.rcx);
+        if irq > 0xDF || bit >= u64::BITS.into() {
+            return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+        }
+        let Some(notification) = self.notifications.get(&id) else {
+            return ControlFlow::Break(Some(TerminationReason::NotFound));
+        };
+        if Some(notification.owner) != self.current_pid() {
+            return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+        }
+        let (irq, bit) = (irq as u8, bit as u8);
+        self.add_irq_handler(irq, IRQTarget::Notification { id, bit })
+    }
+
+    /// Drops the handlers of IRQ `rsi` the current process registered or
+    /// bound to its notifications.
+    pub fn unregister_irq(
+        &mut self,
+        state: &RegisterState,
+    ) -> ControlFlow<Option<TerminationReason>> {
+        let irq = state.rsi;
+        if irq > 0xDF {
+            return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+        }
+        let irq = irq as u8;
+        let pid = self.current_pid().unwrap();
+        let owned: HashSet<_> = self
+            .notifications
+            .iter()
+            .filter(|(_, v)| v.owner == pid)
+            .map(|(id, _)| *id)
+            .collect();
+        let released = self.release_irqs(|v, target| {
+            v == irq
+                && match *target {
+                    IRQTarget::Message(v) => v == pid,
+                    IRQTarget::Notification { id, .. } => owned.contains(&id),
+                }
+        });
+        if released == 0 {
+            return ControlFlow::Break(Some(TerminationReason::NotFound));
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Whether `pid` handles any IRQ, directly or through a notification.
+    pub fn handles_irqs(&self, pid: u64) -> bool {
+        self.irq_handlers
+            .values()
+            .flat_map(|v| &v.handlers)
+            .any(|(target, _)| match *target {
+                IRQTarget::Message(v) => v == pid,
+                IRQTarget::Notification { id, .. } => self.notifications[&id].owner == pid,
+            })
+    }
+
+    /// Adds `target` to the handlers of IRQ `irq`, wiring up the line for
+    /// the first one.
+    fn add_irq_handler(
+        &mut self,
+        irq: u8,
+        target: IRQTarget,
+    ) -> ControlFlow<Option<TerminationReason>> {
+        let line = self.irq_handlers.entry(irq).or_default();
+        if line.handlers.iter().any(|(v, _)| *v == target) {
             return ControlFlow::Break(Some(TerminationReason::AlreadyExists));
         }
+        line.handlers.push((target, false));
+        if line.handlers.len() == 1 {
+            Self::install_irq(irq);
+        }
+        ControlFlow::Continue(())
+    }
 
-        crate::acpi::ioapic::wire_legacy_irq(irq, false);
+    fn install_irq(irq: u8) {
+        crate::acpi::ioapic::wire_irq(irq, false);
         crate::interrupts::idt::set_handler(
             irq + 0x20,
             1,
@@ -279,35 +1007,159 @@ impl Scheduler {
             true,
             true,
         );
+    }
 
+    pub fn set_priority(
+        &mut self,
+        state: &RegisterState,
+    ) -> ControlFlow<Option<TerminationReason>> {
+        let Ok(priority) = ThreadPriority::try_from(state.rsi) else {
+            return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+        };
+        // Otherwise any busy loop could starve the drivers
+        if priority == ThreadPriority::Realtime && !self.handles_irqs(self.current_pid().unwrap()) {
+            return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+        }
+        let thread = self.current_thread_mut().unwrap();
+        thread.priority = priority;
+        thread.effective_priority = priority;
         ControlFlow::Continue(())
     }
 
-    pub fn thread_teardown(&mut self) -> ControlFlow<Option<TerminationReason>> {
-        let id = self.current_tid.take().unwrap();
-        self.threads.remove(&id);
-        self.tid_gen.free(id);
+    /// Blocks the current thread for `rsi` milliseconds.
+    pub fn sleep(&mut self, state: &RegisterState) -> ControlFlow<Option<TerminationReason>> {
+        let ms = state.rsi;
+        if ms != 0 {
+            let deadline = self.now().saturating_add(ms);
+            let thread = self.current_thread_mut().unwrap();
+            thread.state = ThreadState::Blocked(WaitReason::Sleep);
+            let tid = thread.id;
+            self.timers.insert(deadline, tid);
+        }
+        ControlFlow::Break(None)
+    }
 
-        let proc = self.current_process_mut().unwrap();
+    /// Ends the current thread, handing `code` to whoever joins it.
+    pub fn thread_teardown(&mut self, code: u64) -> ControlFlow<Option<TerminationReason>> {
+        let id = PerCpu::current().current_tid.take().unwrap();
+        self.dequeue(id);
+        let thread = self.threads.remove(&id).unwrap();
+
+        let pid = self.current_pid().unwrap();
+        let proc = self.processes.get_mut(&pid).unwrap();
         proc.thread_ids.remove(&id);
+        let detached = proc.detached_threads.remove(&id);
         if proc.thread_ids.is_empty() {
-            let pid = self.current_pid.take().unwrap();
-            self.processes.remove(&pid);
-            self.pid_gen.free(pid);
+            proc.dying = Some(ExitReason::Quit(code));
+            self.tid_gen.free(id);
+            self.leave_dying();
+            return ControlFlow::Break(None);
+        }
+        proc.free_alloc(thread.stack_addr);
+
+        let joiners: Vec<_> = proc
+            .thread_ids
+            .iter()
+            .copied()
+            .filter(|v| self.threads[v].state == ThreadState::Blocked(WaitReason::Join(id)))
+            .collect();
+        if joiners.is_empty() && !detached {
+            proc.exited_threads.insert(id, code);
+            return ControlFlow::Break(None);
+        }
+
+        self.tid_gen.free(id);
+        for tid in joiners {
+            let thread = self.threads.get_mut(&tid).unwrap();
+            thread.regs.rax = code;
+            thread.state = ThreadState::Inactive;
+            self.wake(tid);
         }
 
         ControlFlow::Break(None)
     }
 
-    pub fn process_teardown(&mut self) {
-        // TODO: Teardown any residual messages too.
-        self.current_tid = None;
-        let pid = self.current_pid.take().unwrap();
-        let proc = self.processes.remove(&pid).unwrap();
-        for tid in &proc.thread_ids {
-            self.threads.remove(tid);
+    /// Ends the current process with all of its threads. Cores running the
+    /// others get told to reschedule, the process is dropped once the last
+    /// of them left it.
+    pub fn process_teardown(&mut self, reason: ExitReason) {
+        let cpu = PerCpu::current();
+        let pid = cpu.current_pid.unwrap();
+        let proc = &self.processes[&pid];
+        if proc.dying.is_none() {
+            let waiting: Vec<_> = proc
+                .thread_ids
+                .iter()
+                .copied()
+                .filter(|v| self.threads[v].state != ThreadState::Active)
+                .collect();
+            let running: Vec<_> = proc
+                .active_cores
+                .iter()
+                .copied()
+                .filter(|&v| v != cpu.id)
+                .collect();
+            self.processes.get_mut(&pid).unwrap().dying = Some(reason);
+            for tid in waiting {
+                self.drop_thread(pid, tid);
+            }
+            for core in running {
+                cpu.lapic
+                    .send_ipi(InterruptCommand::new().with_vector(128).with_dest(core));
+            }
+        }
+        self.leave_dying();
+    }
+
+    /// Takes this core off the dying current process, dropping the thread
+    /// it ran. The last core to leave drops the process.
+    fn leave_dying(&mut self) {
+        let cpu = PerCpu::current();
+        let pid = cpu.current_pid.take().unwrap();
+        if let Some(tid) = cpu.current_tid.take() {
+            self.drop_thread(pid, tid);
+        }
+        // The address space is about to go away
+        Self::load_kernel_pml4();
+        let proc = self.processes.get_mut(&pid).unwrap();
+        proc.active_cores.remove(&cpu.id);
+        if !proc.active_cores.is_empty() {
+            return;
+        }
+
+        let mut proc = self.processes.remove(&pid).unwrap();
+        for tid in core::mem::take(&mut proc.thread_ids) {
+            self.dequeue(tid);
+            self.threads.remove(&tid);
+            self.tid_gen.free(tid);
+        }
+        for tid in proc.exited_threads.keys() {
             self.tid_gen.free(*tid);
         }
+        let reason = proc.dying.take().unwrap();
+        self.process_exited(proc, reason);
         self.pid_gen.free(pid);
     }
+
+    fn drop_thread(&mut self, pid: u64, tid: u64) {
+        self.dequeue(tid);
+        self.threads.remove(&tid);
+        self.processes
+            .get_mut(&pid)
+            .unwrap()
+            .thread_ids
+            .remove(&tid);
+        self.tid_gen.free(tid);
+    }
+
+    fn load_kernel_pml4() {
+        unsafe {
+            (*crate::system::state::SYS_STATE.get())
+                .pml4
+                .as_ref()
+                .unwrap()
+                .lock()
+                .set_cr3();
+        }
+    }
 }
@@ -0,0 +1,102 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use hashbrown::HashMap;
+
+const SLOTS: usize = 256;
+
+/// Hashed timer wheel keyed by millisecond tick, each slot holds `(deadline, tid)` pairs.
+/// Deadlines more than a lap away just stay in their slot until due.
+pub struct TimerWheel {
+    slots: [Vec<(u64, u64)>; SLOTS],
+    now: u64,
+    /// Deadline of every armed thread, which also gives its slot.
+    armed: HashMap<u64, u64>,
+    /// How many threads are due at each deadline, the first is the earliest.
+    deadlines: BTreeMap<u64, usize>,
+}
+
+impl TimerWheel {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            slots: [const { Vec::new() }; SLOTS],
+            now: 0,
+            armed: HashMap::new(),
+            deadlines: BTreeMap::new(),
+        }
+    }
+
+    #[inline]
+    pub const fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn insert(&mut self, deadline: u64, tid: u64) {
+        self.cancel(tid);
+        let deadline = deadline.max(self.now + 1);
+        self.slots[deadline as usize % SLOTS].push((deadline, tid));
+        self.armed.insert(tid, deadline);
+        *self.deadlines.entry(deadline).or_default() += 1;
+    }
+
+    pub fn cancel(&mut self, tid: u64) {
+        let Some(deadline) = self.armed.remove(&tid) else {
+            return;
+        };
+        let slot = &mut self.slots[deadline as usize % SLOTS];
+        let i = slot.iter().position(|(_, v)| *v == tid).unwrap();
+        slot.swap_remove(i);
+        self.disarm(deadline);
+    }
+
+    fn disarm(&mut self, deadline: u64) {
+        let count = self.deadlines.get_mut(&deadline).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.deadlines.remove(&deadline);
+        }
+    }
+
+    /// Moves time forward to `now` and returns the threads that are due.
+    /// Skipped ticks are caught up on, a gap of a full lap or more scans
+    /// every slot once.
+    pub fn advance_to(&mut self, now: u64) -> Vec<u64> {
+        if now <= self.now {
+            return Vec::new();
+        }
+
+        let mut expired = Vec::new();
+        let slots = (now - self.now).min(SLOTS as u64);
+        for tick in self.now + 1..=self.now + slots {
+            self.slots[tick as usize % SLOTS].retain(|&(deadline, tid)| {
+                if deadline <= now {
+                    expired.push((deadline, tid));
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        self.now = now;
+        expired
+            .into_iter()
+            .map(|(deadline, tid)| {
+                self.armed.remove(&tid);
+                self.disarm(deadline);
+                tid
+            })
+            .collect()
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.deadlines.first_key_value().map(|(v, _)| *v)
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
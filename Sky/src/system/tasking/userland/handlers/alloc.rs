@@ -4,7 +4,10 @@ use core::ops::ControlFlow;
 
 use skykit::TerminationReason;
 
-use crate::system::{tasking::scheduler::Scheduler, RegisterState};
+use crate::system::{
+    tasking::{scheduler::Scheduler, AllocationType},
+    RegisterState,
+};
 
 pub fn alloc(
     scheduler: &mut Scheduler,
@@ -28,7 +31,12 @@ pub fn free(
     let addr = state.rsi;
 
     let process = scheduler.current_process_mut().unwrap();
-    if process.is_msg(addr) {
+    if process.is_msg(addr)
+        || matches!(
+            process.allocations.get(&addr),
+            Some((_, AllocationType::Shared { .. }))
+        )
+    {
         return ControlFlow::Break(Some(TerminationReason::MalformedAddress));
     }
 
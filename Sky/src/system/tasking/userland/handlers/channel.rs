@@ -0,0 +1,160 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use alloc::vec::Vec;
+use core::ops::ControlFlow;
+
+use amd64::paging::PAGE_SIZE;
+use skykit::{
+    channel::{CHANNEL_DEPTH, MAX_CHANNEL_PAYLOAD},
+    msg::{Endpoint, Message},
+    TerminationReason,
+};
+
+use super::service::read_name;
+use crate::system::{
+    tasking::{scheduler::Scheduler, Channel},
+    RegisterState,
+};
+
+/// Creates the channel named at `rsi` of `rdx` bytes with the current
+/// process as its publisher. Returns its ID, or zero if the name is taken.
+pub fn create(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let name = match read_name(scheduler, state.rsi, state.rdx) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    if scheduler.channels.values().any(|v| v.name == name) {
+        state.rax = 0;
+        return ControlFlow::Continue(());
+    }
+
+    let publisher = scheduler.current_pid().unwrap();
+    debug!("PID {publisher} created channel {name}");
+    let id = scheduler.channel_gen.next();
+    scheduler.channels.insert(
+        id,
+        Channel {
+            name,
+            publisher,
+            subscribers: Vec::new(),
+        },
+    );
+    state.rax = id;
+    ControlFlow::Continue(())
+}
+
+/// Subscribes the current process to the channel named at `rsi` of `rdx`
+/// bytes. Returns a new endpoint the payloads arrive on as messages, or zero
+/// if there is no such channel. Destroying the endpoint unsubscribes.
+pub fn subscribe(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let name = match read_name(scheduler, state.rsi, state.rdx) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    let Some(id) = scheduler
+        .channels
+        .iter()
+        .find(|(_, v)| v.name == name)
+        .map(|(id, _)| *id)
+    else {
+        state.rax = 0;
+        return ControlFlow::Continue(());
+    };
+
+    let pid = scheduler.current_pid().unwrap();
+    let endpoint = scheduler.endpoint_gen.next();
+    scheduler.endpoints.insert(endpoint, pid);
+    scheduler
+        .channels
+        .get_mut(&id)
+        .unwrap()
+        .subscribers
+        .push(endpoint);
+    state.rax = endpoint;
+    ControlFlow::Continue(())
+}
+
+/// Makes room for one more payload on `endpoint` of `pid` by dropping the
+/// oldest one queued if there are [`CHANNEL_DEPTH`] already. Sends held back
+/// by the full queue get the freed space.
+fn drop_oldest(scheduler: &mut Scheduler, pid: u64, endpoint: u64) {
+    let process = scheduler.processes.get_mut(&pid).unwrap();
+    let queued = process
+        .messages
+        .iter()
+        .filter(|v| v.endpoint.0 == endpoint)
+        .count();
+    if queued < CHANNEL_DEPTH {
+        return;
+    }
+    // New messages go to the front
+    let i = process
+        .messages
+        .iter()
+        .rposition(|v| v.endpoint.0 == endpoint)
+        .unwrap();
+    let msg = process.messages.remove(i).unwrap();
+    process.free_msg(msg.id);
+    process.queue_dropped += 1;
+    scheduler.message_sources.remove(&msg.id);
+    scheduler.msg_id_gen.free(msg.id);
+    super::msg::admit_held(scheduler, pid);
+}
+
+/// Copies the payload at `rdx` of `rcx` bytes to every subscriber of
+/// channel `rsi`, which only its publisher may do.
+pub fn publish(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let (id, addr, size) = (state.rsi, state.rdx, state.rcx);
+    let pid = scheduler.current_pid().unwrap();
+    let Some(channel) = scheduler.channels.get(&id) else {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    };
+    if channel.publisher != pid {
+        return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+    }
+    if size > MAX_CHANNEL_PAYLOAD {
+        return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+    }
+    if !scheduler
+        .current_process()
+        .unwrap()
+        .region_is_valid(addr, size)
+    {
+        return ControlFlow::Break(Some(TerminationReason::MalformedAddress));
+    }
+
+    let data = unsafe { core::slice::from_raw_parts(addr as *const u8, size as _) }.to_vec();
+    for endpoint in channel.subscribers.clone() {
+        let target = scheduler.endpoints[&endpoint];
+        drop_oldest(scheduler, target, endpoint);
+
+        let process = scheduler.processes.get_mut(&target).unwrap();
+        let (virt, page_count) = process.allocate(size.max(1));
+        unsafe {
+            let dst =
+                (virt - skykit::USER_VIRT_OFFSET + amd64::paging::PHYS_VIRT_OFFSET) as *mut u8;
+            core::ptr::write_bytes(dst, 0, (page_count * PAGE_SIZE) as _);
+            core::ptr::copy_nonoverlapping(data.as_ptr(), dst, data.len());
+        }
+        let mut msg = Message::new(scheduler.msg_id_gen.next(), pid, unsafe {
+            core::slice::from_raw_parts(virt as *const _, size as _)
+        });
+        msg.endpoint = Endpoint(endpoint);
+        process.track_msg(msg.id, virt);
+        // The copy is the subscriber's to free
+        scheduler.message_sources.insert(msg.id, (target, target));
+
+        let tids = scheduler.processes[&target].thread_ids.clone();
+        let _ = super::msg::handle_new(scheduler, target, tids, msg, false);
+    }
+    ControlFlow::Continue(())
+}
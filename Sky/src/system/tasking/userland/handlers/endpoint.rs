@@ -0,0 +1,50 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use core::ops::ControlFlow;
+
+use skykit::TerminationReason;
+
+use crate::system::{tasking::scheduler::Scheduler, RegisterState};
+
+/// Gives the current process another endpoint to receive on, its ID is
+/// returned in `rax`.
+pub fn create(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let pid = scheduler.current_pid().unwrap();
+    let id = scheduler.endpoint_gen.next();
+    scheduler.endpoints.insert(id, pid);
+    state.rax = id;
+    ControlFlow::Continue(())
+}
+
+/// Drops endpoint `rsi` of the current process, along with the channel
+/// subscription it may be for. Messages already queued on it stay queued.
+pub fn destroy(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let id = state.rsi;
+    let Some(&owner) = scheduler.endpoints.get(&id) else {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    };
+    if scheduler.current_pid() != Some(owner) {
+        return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+    }
+    scheduler.remove_endpoint(id);
+    ControlFlow::Continue(())
+}
+
+/// Resolves the destination of a message, `endpoint` if it is not the
+/// default one, otherwise `pid`.
+pub fn resolve(scheduler: &Scheduler, pid: u64, endpoint: u64) -> Result<u64, TerminationReason> {
+    if endpoint == 0 {
+        return Ok(pid);
+    }
+    scheduler
+        .endpoints
+        .get(&endpoint)
+        .copied()
+        .ok_or(TerminationReason::NotFound)
+}
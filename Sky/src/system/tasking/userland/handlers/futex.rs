@@ -0,0 +1,93 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use core::{
+    ops::ControlFlow,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use skykit::{syscall::FutexWaitResult, TerminationReason};
+
+use crate::system::{
+    tasking::{scheduler::Scheduler, ThreadState, WaitReason},
+    RegisterState,
+};
+
+/// Physical address behind the futex word at `addr`, so processes sharing
+/// the page end up on the same wait queue.
+fn futex_key(scheduler: &Scheduler, addr: u64) -> Option<u64> {
+    if !addr.is_multiple_of(4) {
+        return None;
+    }
+    let (phys, flags) = unsafe {
+        scheduler
+            .current_process()
+            .unwrap()
+            .cr3
+            .lock()
+            .virt_to_phys(addr)
+    }?;
+    flags.user.then_some(phys)
+}
+
+/// Blocks until woken if the word at `rsi` still holds `rdx`, for at most
+/// `rcx` milliseconds unless that is zero.
+pub fn wait(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let (addr, expected, timeout) = (state.rsi, state.rdx as u32, state.rcx);
+    let Some(key) = futex_key(scheduler, addr) else {
+        return ControlFlow::Break(Some(TerminationReason::MalformedAddress));
+    };
+
+    let word = unsafe { &*((key + amd64::paging::PHYS_VIRT_OFFSET) as *const AtomicU32) };
+    if word.load(Ordering::SeqCst) != expected {
+        state.rax = FutexWaitResult::ValueMismatch as u64;
+        return ControlFlow::Continue(());
+    }
+
+    state.rax = FutexWaitResult::Woken as u64;
+    let thread = scheduler.current_thread_mut().unwrap();
+    thread.state = ThreadState::Blocked(WaitReason::Futex(key));
+    let tid = thread.id;
+    scheduler.futexes.entry(key).or_default().push_back(tid);
+    if timeout != 0 {
+        let deadline = scheduler.now().saturating_add(timeout);
+        scheduler.timers.insert(deadline, tid);
+    }
+    ControlFlow::Break(None)
+}
+
+/// Wakes up to `rdx` threads waiting on the word at `rsi`, returns how many.
+pub fn wake(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let (addr, count) = (state.rsi, state.rdx);
+    let Some(key) = futex_key(scheduler, addr) else {
+        return ControlFlow::Break(Some(TerminationReason::MalformedAddress));
+    };
+
+    let mut woken = 0;
+    while woken < count {
+        let Some(tid) = scheduler.futexes.get_mut(&key).and_then(|v| v.pop_front()) else {
+            break;
+        };
+        let Some(thread) = scheduler.threads.get_mut(&tid) else {
+            continue;
+        };
+        if thread.state != ThreadState::Blocked(WaitReason::Futex(key)) {
+            continue;
+        }
+        thread.state = ThreadState::Inactive;
+        scheduler.timers.cancel(tid);
+        scheduler.wake(tid);
+        woken += 1;
+    }
+    if scheduler.futexes.get(&key).is_some_and(|v| v.is_empty()) {
+        scheduler.futexes.remove(&key);
+    }
+
+    state.rax = woken;
+    ControlFlow::Continue(())
+}
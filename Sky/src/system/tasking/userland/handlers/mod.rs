@@ -7,9 +7,18 @@ use skykit::TerminationReason;
 use crate::system::{tasking::scheduler::Scheduler, RegisterState};
 
 pub mod alloc;
+pub mod channel;
+pub mod endpoint;
+pub mod futex;
 pub mod msg;
+pub mod notification;
 pub mod os_dt_entry;
 pub mod port;
+pub mod process;
+pub mod service;
+pub mod shared_mem;
+pub mod thread;
+pub mod time;
 
 pub fn kprint(
     scheduler: &Scheduler,
@@ -1,75 +1,140 @@
 // Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
 
+use alloc::vec::Vec;
 use core::ops::ControlFlow;
 
 use amd64::paging::{PageTableFlags, PAGE_SIZE};
 use hashbrown::HashSet;
 use skykit::{
-    msg::{KernelMessage, Message},
+    msg::{
+        Endpoint, KernelMessage, Message, QueueFullPolicy, RecvFilter, SendFlags, SendResult,
+        MAX_QUEUE_LIMIT,
+    },
     TerminationReason,
 };
 
 use crate::system::{
-    tasking::{scheduler::Scheduler, ThreadState},
+    tasking::{
+        scheduler::{IRQTarget, Scheduler},
+        AllocationType, HeldSend, ThreadState, WaitReason,
+    },
     RegisterState,
 };
 
+/// Loads `msg` into the registers a thread returns from `MsgRecv` with.
+/// Threads that entered through `int 249` keep their `r8` and `r9`.
+fn deliver(regs: &mut RegisterState, msg: &Message) {
+    regs.rax = msg.id;
+    regs.rdi = msg.pid;
+    regs.rsi = msg.data.as_ptr() as _;
+    regs.rdx = msg.data.len() as _;
+    if super::super::is_syscall(regs) {
+        regs.r8 = msg.reply_token.unwrap_or_default();
+        regs.r9 = msg.endpoint.0;
+    }
+}
+
+/// Most endpoints a single `MsgRecv` can wait on.
+const MAX_RECV_ENDPOINTS: u64 = 64;
+
 pub fn handle_new(
     scheduler: &mut Scheduler,
     pid: u64,
     tids: HashSet<u64>,
     msg: Message,
+    boost: bool,
 ) -> ControlFlow<Option<TerminationReason>> {
-    let idle = scheduler.current_tid.is_none();
+    let idle = scheduler.current_tid().is_none();
     for tid in tids {
         let thread = scheduler.threads.get_mut(&tid).unwrap();
-        if !thread.state.is_suspended() {
+        if !thread.state.is_suspended() || !thread.takes(msg.pid, msg.endpoint.0) {
             continue;
         }
         thread.state = ThreadState::Inactive;
-        thread.regs.rax = msg.id;
-        thread.regs.rdi = msg.pid;
-        thread.regs.rsi = msg.data.as_ptr() as _;
-        thread.regs.rdx = msg.data.len() as _;
-        if idle {
+        deliver(&mut thread.regs, &msg);
+        scheduler.timers.cancel(tid);
+        scheduler.wake(tid);
+        let preempt = boost && scheduler.boost(tid);
+        if idle || preempt {
             return ControlFlow::Break(None);
         }
         return ControlFlow::Continue(());
     }
     let process = scheduler.processes.get_mut(&pid).unwrap();
-    process.messages.push_front(msg);
+    process.push_message(msg);
     ControlFlow::Continue(())
 }
 
-pub fn send(
-    scheduler: &mut Scheduler,
-    state: &RegisterState,
-) -> ControlFlow<Option<TerminationReason>> {
-    let src = scheduler.current_pid.unwrap();
-    let target = state.rsi;
+/// Makes sure `src` may turn its buffer at `addr` into a message to `target`.
+fn check_post(
+    scheduler: &Scheduler,
+    src: u64,
+    target: u64,
+    addr: u64,
+    size: u64,
+    move_pages: bool,
+) -> Result<(), TerminationReason> {
     if src == target {
-        return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+        return Err(TerminationReason::MalformedArgument);
     }
 
-    let (addr, size) = (state.rdx, state.rcx);
-    if !scheduler
-        .current_process()
-        .unwrap()
-        .region_is_within_bounds(addr, size)
+    let process = &scheduler.processes[&src];
+    // Acknowledging the message frees the buffer, which isn't the sender's
+    // to free if it is shared or still part of another message
+    if !process.region_is_within_bounds(addr, size)
+        || matches!(
+            process.allocations.get(&addr),
+            Some((_, AllocationType::Shared { .. }))
+        )
+        || process.is_msg(addr)
     {
-        return ControlFlow::Break(Some(TerminationReason::MalformedAddress));
+        return Err(TerminationReason::MalformedAddress);
+    }
+    if move_pages && process.allocations[&addr].1 != AllocationType::Writable {
+        return Err(TerminationReason::MalformedAddress);
     }
 
     if !scheduler.processes.contains_key(&target) {
-        return ControlFlow::Break(Some(TerminationReason::NotFound));
+        return Err(TerminationReason::NotFound);
     }
+    Ok(())
+}
 
-    let msg = Message::new(scheduler.msg_id_gen.next(), src, unsafe {
+/// Turns the buffer of `src` at `addr` into a message to `endpoint` of
+/// `target` and maps it there. If `move_pages` is set, the whole allocation
+/// moves to the target instead, which frees it on acknowledging.
+fn post(
+    scheduler: &mut Scheduler,
+    src: u64,
+    target: u64,
+    endpoint: u64,
+    addr: u64,
+    size: u64,
+    move_pages: bool,
+) -> Result<Message, TerminationReason> {
+    check_post(scheduler, src, target, addr, size, move_pages)?;
+
+    let mut msg = Message::new(scheduler.msg_id_gen.next(), src, unsafe {
         core::slice::from_raw_parts(addr as *const _, size as _)
     });
-    scheduler.message_sources.insert(msg.id, src);
+    msg.endpoint = Endpoint(endpoint);
+
+    if move_pages {
+        let (alloc_size, ty) = scheduler
+            .processes
+            .get_mut(&src)
+            .unwrap()
+            .untrack_alloc(addr);
+        let process = scheduler.processes.get_mut(&target).unwrap();
+        process.track_alloc(addr, alloc_size, ty);
+        process.track_msg(msg.id, addr);
+        scheduler.message_sources.insert(msg.id, (target, target));
+        return Ok(msg);
+    }
+
+    scheduler.message_sources.insert(msg.id, (src, target));
 
-    let cur = scheduler.current_process_mut().unwrap();
+    let cur = scheduler.processes.get_mut(&src).unwrap();
 
     cur.track_msg(msg.id, addr);
 
@@ -82,56 +147,391 @@ pub fn send(
             PageTableFlags::new_present().with_user(true),
         );
     }
-    let tids = process.thread_ids.clone();
-    handle_new(scheduler, target, tids, msg)
+    Ok(msg)
 }
 
-pub fn recv(
+/// Whether a message from `src` to `endpoint` of `target` would have to
+/// wait for space in its queue, no thread of it waiting for one like that.
+fn must_queue(scheduler: &Scheduler, src: u64, target: u64, endpoint: u64) -> bool {
+    let process = &scheduler.processes[&target];
+    process.queue_full()
+        && !process.thread_ids.iter().any(|v| {
+            let thread = &scheduler.threads[v];
+            thread.state.is_suspended() && thread.takes(src, endpoint)
+        })
+}
+
+/// Returns `result` of a send in `rax`, which `int 249` callers keep.
+pub const fn set_send_result(regs: &mut RegisterState, result: SendResult) {
+    if super::super::is_syscall(regs) {
+        regs.rax = result as u64;
+    }
+}
+
+/// Holds back the send or call of the current thread until `target` has
+/// space in its queue.
+fn hold(scheduler: &mut Scheduler, target: u64, mut held: HeldSend) {
+    let thread = scheduler.current_thread_mut().unwrap();
+    thread.state = ThreadState::Blocked(WaitReason::QueueSpace(target));
+    held.tid = thread.id;
+    scheduler
+        .held_sends
+        .entry(target)
+        .or_default()
+        .push_back(held);
+}
+
+/// The endpoint in `r8`, zero for the default one through `int 249`.
+const fn endpoint_arg(state: &RegisterState) -> u64 {
+    if super::super::is_syscall(state) {
+        state.r8
+    } else {
+        0
+    }
+}
+
+/// Sends the buffer at `rdx` of `rcx` bytes to endpoint `r8`, or the default
+/// endpoint of PID `rsi` if that is zero. `r9` holds the [`SendFlags`], which
+/// are the defaults through `int 249`. The [`SendResult`] is returned in `rax`
+/// unless called through `int 249`.
+pub fn send(
     scheduler: &mut Scheduler,
     state: &mut RegisterState,
 ) -> ControlFlow<Option<TerminationReason>> {
-    let process = scheduler.current_process_mut().unwrap();
-    let Some(msg) = process.messages.pop_back() else {
-        scheduler.current_thread_mut().unwrap().state = ThreadState::Suspended;
+    let flags = if super::super::is_syscall(state) {
+        let Some(v) = SendFlags::from_raw(state.r9) else {
+            return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+        };
+        v
+    } else {
+        SendFlags::default()
+    };
+    let endpoint = endpoint_arg(state);
+    let target = match super::endpoint::resolve(scheduler, state.rsi, endpoint) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    let (src, addr, size) = (scheduler.current_pid().unwrap(), state.rdx, state.rcx);
+    if let Err(e) = check_post(scheduler, src, target, addr, size, flags.move_pages) {
+        return ControlFlow::Break(Some(e));
+    }
+
+    if must_queue(scheduler, src, target, endpoint) {
+        match flags.on_full {
+            QueueFullPolicy::Block => {
+                set_send_result(state, SendResult::Sent);
+                hold(
+                    scheduler,
+                    target,
+                    HeldSend {
+                        tid: 0,
+                        endpoint,
+                        addr,
+                        size,
+                        move_pages: flags.move_pages,
+                        call: false,
+                    },
+                );
+                return ControlFlow::Break(None);
+            }
+            QueueFullPolicy::Fail => {
+                set_send_result(state, SendResult::QueueFull);
+                return ControlFlow::Continue(());
+            }
+            QueueFullPolicy::Drop => {
+                scheduler.processes.get_mut(&target).unwrap().queue_dropped += 1;
+                set_send_result(state, SendResult::Dropped);
+                return ControlFlow::Continue(());
+            }
+        }
+    }
+
+    let msg = match post(
+        scheduler,
+        src,
+        target,
+        endpoint,
+        addr,
+        size,
+        flags.move_pages,
+    ) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    set_send_result(state, SendResult::Sent);
+    let tids = scheduler.processes[&target].thread_ids.clone();
+    handle_new(scheduler, target, tids, msg, false)
+}
+
+/// Lets sends held back by the full queue of `pid` through while there is
+/// space. Senders whose buffer went away in the meantime get
+/// [`SendResult::Dropped`], callers an empty reply.
+pub fn admit_held(scheduler: &mut Scheduler, pid: u64) {
+    while !scheduler.processes[&pid].queue_full() {
+        let Some(queue) = scheduler.held_sends.get_mut(&pid) else {
+            return;
+        };
+        let held = queue.pop_front().unwrap();
+        if queue.is_empty() {
+            scheduler.held_sends.remove(&pid);
+        }
+
+        let src = scheduler.threads[&held.tid].pid;
+        let result = match post(
+            scheduler,
+            src,
+            pid,
+            held.endpoint,
+            held.addr,
+            held.size,
+            held.move_pages,
+        ) {
+            Ok(msg) if held.call => {
+                start_call(scheduler, held.tid, pid, msg);
+                continue;
+            }
+            Ok(msg) => {
+                let tids = scheduler.processes[&pid].thread_ids.clone();
+                let _ = handle_new(scheduler, pid, tids, msg, false);
+                SendResult::Sent
+            }
+            Err(_) => SendResult::Dropped,
+        };
+        let thread = scheduler.threads.get_mut(&held.tid).unwrap();
+        if held.call {
+            deliver(&mut thread.regs, &Message::new(0, pid, &[]));
+        } else {
+            set_send_result(&mut thread.regs, result);
+        }
+        thread.state = ThreadState::Inactive;
+        scheduler.wake(held.tid);
+    }
+}
+
+/// Sets the queue depth of the current process to `rsi`.
+pub fn set_queue_limit(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let limit = state.rsi;
+    if limit == 0 || limit > MAX_QUEUE_LIMIT {
+        return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+    }
+    let pid = scheduler.current_pid().unwrap();
+    scheduler.processes.get_mut(&pid).unwrap().queue_limit = limit as usize;
+    admit_held(scheduler, pid);
+    ControlFlow::Continue(())
+}
+
+/// Returns the length, high-water mark, depth and drop count of the current
+/// process' queue in `rax`, `rdi`, `rsi` and `rdx`.
+pub fn queue_stats(
+    scheduler: &Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let process = scheduler.current_process().unwrap();
+    state.rax = process.messages.len() as _;
+    state.rdi = process.queue_high_water as _;
+    state.rsi = process.queue_limit as _;
+    state.rdx = process.queue_dropped;
+    ControlFlow::Continue(())
+}
+
+/// Attaches a fresh reply token to `msg` from thread `tid` and hands it to
+/// `target`, blocking the caller until the answer.
+fn start_call(scheduler: &mut Scheduler, tid: u64, target: u64, mut msg: Message) {
+    scheduler.last_reply_token += 1;
+    let token = scheduler.last_reply_token;
+    msg.reply_token = Some(token);
+
+    scheduler.threads.get_mut(&tid).unwrap().state = ThreadState::Blocked(WaitReason::Reply(token));
+    scheduler.pending_replies.insert(token, (tid, target));
+
+    let tids = scheduler.processes[&target].thread_ids.clone();
+    // The caller is blocked now, so it gets switched away from regardless
+    let _ = handle_new(scheduler, target, tids, msg, false);
+}
+
+/// Sends like [`send`] with a fresh reply token attached, then blocks the
+/// caller until the target answers through [`reply`]. Waits for space
+/// first if the target's queue is full.
+pub fn call(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let endpoint = endpoint_arg(state);
+    let target = match super::endpoint::resolve(scheduler, state.rsi, endpoint) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    let (src, addr, size) = (scheduler.current_pid().unwrap(), state.rdx, state.rcx);
+    if let Err(e) = check_post(scheduler, src, target, addr, size, false) {
+        return ControlFlow::Break(Some(e));
+    }
+
+    if must_queue(scheduler, src, target, endpoint) {
+        hold(
+            scheduler,
+            target,
+            HeldSend {
+                tid: 0,
+                endpoint,
+                addr,
+                size,
+                move_pages: false,
+                call: true,
+            },
+        );
         return ControlFlow::Break(None);
+    }
+
+    let msg = match post(scheduler, src, target, endpoint, addr, size, false) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
     };
+    let tid = scheduler.current_tid().unwrap();
+    start_call(scheduler, tid, target, msg);
+    ControlFlow::Break(None)
+}
 
-    state.rax = msg.id;
-    state.rdi = msg.pid;
-    state.rsi = msg.data.as_ptr() as u64;
-    state.rdx = msg.data.len() as u64;
+/// Answers the call that handed out reply token `rsi`. Tokens are one-shot
+/// and only valid for the process the call went to.
+pub fn reply(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let token = state.rsi;
+    let Some(&(tid, callee)) = scheduler.pending_replies.get(&token) else {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    };
+    if scheduler.current_pid() != Some(callee) {
+        return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+    }
+
+    let caller = scheduler.threads[&tid].pid;
+    let msg = match post(scheduler, callee, caller, 0, state.rdx, state.rcx, false) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    scheduler.pending_replies.remove(&token);
+
+    let thread = scheduler.threads.get_mut(&tid).unwrap();
+    thread.state = ThreadState::Inactive;
+    deliver(&mut thread.regs, &msg);
+    scheduler.wake(tid);
     ControlFlow::Continue(())
 }
 
+fn read_endpoints(
+    scheduler: &Scheduler,
+    addr: u64,
+    count: u64,
+) -> Result<Vec<u64>, TerminationReason> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if count > MAX_RECV_ENDPOINTS {
+        return Err(TerminationReason::MalformedArgument);
+    }
+    if !scheduler
+        .current_process()
+        .unwrap()
+        .region_is_valid(addr, count * 8)
+    {
+        return Err(TerminationReason::MalformedAddress);
+    }
+    Ok(unsafe { core::slice::from_raw_parts(addr as *const u64, count as _) }.to_vec())
+}
+
+/// Takes the oldest message that matches the [`RecvFilter`] in `rsi` and
+/// is for one of the `r9` endpoint IDs at `r8`, or any endpoint if `r9` is
+/// zero. If there is none, returns no message right away if `rcx` is
+/// non-zero, or waits, for at most `rdx` milliseconds unless that is zero.
+/// Through `int 249`, waits for any message.
+pub fn recv(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let (filter, timeout, non_blocking, endpoints) = if super::super::is_syscall(state) {
+        match read_endpoints(scheduler, state.r8, state.r9) {
+            Ok(v) => (
+                RecvFilter::from_raw(state.rsi),
+                state.rdx,
+                state.rcx != 0,
+                v,
+            ),
+            Err(e) => return ControlFlow::Break(Some(e)),
+        }
+    } else {
+        (RecvFilter::Any, 0, false, Vec::new())
+    };
+
+    let thread = scheduler.current_thread_mut().unwrap();
+    thread.recv_filter = filter;
+    thread.recv_endpoints = endpoints;
+    let (tid, pid) = (thread.id, thread.pid);
+    let thread = &scheduler.threads[&tid];
+    let process = scheduler.processes.get_mut(&pid).unwrap();
+    // New messages go to the front
+    let msg = process
+        .messages
+        .iter()
+        .rposition(|v| thread.takes(v.pid, v.endpoint.0))
+        .and_then(|i| process.messages.remove(i));
+    if let Some(msg) = msg {
+        deliver(state, &msg);
+        admit_held(scheduler, pid);
+        return ControlFlow::Continue(());
+    }
+
+    if non_blocking {
+        deliver(state, &Message::new(0, 0, &[]));
+        return ControlFlow::Continue(());
+    }
+
+    scheduler.threads.get_mut(&tid).unwrap().state = ThreadState::Suspended;
+    if timeout != 0 {
+        let deadline = scheduler.now().saturating_add(timeout);
+        scheduler.timers.insert(deadline, tid);
+    }
+    ControlFlow::Break(None)
+}
+
 pub fn ack(
     scheduler: &mut Scheduler,
     state: &RegisterState,
 ) -> ControlFlow<Option<TerminationReason>> {
     let msg_id = state.rsi;
 
-    let Some(src_pid) = scheduler.message_sources.remove(&msg_id) else {
+    let cur_pid = scheduler.current_pid().unwrap();
+    let Some(&(src_pid, receiver)) = scheduler.message_sources.get(&msg_id) else {
         return ControlFlow::Break(Some(TerminationReason::NotFound));
     };
+    if receiver != cur_pid {
+        return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+    }
+    scheduler.message_sources.remove(&msg_id);
 
-    let cur_pid = scheduler.current_pid.unwrap();
+    // Kernel messages and moved buffers are the receiver's to free
     let pid = if src_pid == 0 { cur_pid } else { src_pid };
     let process = scheduler.processes.get_mut(&pid).unwrap();
     let addr = *process.msg_id_to_addr.get(&msg_id).unwrap();
     let size = process.allocations.get(&addr).copied().unwrap().0;
-    if src_pid == 0 {
-        let msg: KernelMessage = unsafe {
-            postcard::from_bytes(core::slice::from_raw_parts(addr as *const _, size as _)).unwrap()
-        };
-        let KernelMessage::IRQFired(irq) = msg;
-        crate::acpi::ioapic::set_irq_mask(irq, false);
-    }
+    let kernel_msg = (src_pid == 0).then(|| unsafe {
+        postcard::from_bytes::<KernelMessage>(core::slice::from_raw_parts(
+            addr as *const _,
+            size as _,
+        ))
+        .unwrap()
+    });
     process.free_msg(msg_id);
     scheduler.msg_id_gen.free(msg_id);
+    if let Some(KernelMessage::IRQFired(irq)) = kernel_msg {
+        scheduler.ack_irq(irq, IRQTarget::Message(cur_pid));
+    }
     if pid != cur_pid {
         let process = scheduler.current_process().unwrap();
-        unsafe {
-            process.cr3.lock().unmap(addr, (size + 0xFFF) / 0x1000);
-        }
+        unsafe { process.unmap(addr, (size + 0xFFF) / 0x1000) }
     }
 
     ControlFlow::Continue(())
@@ -0,0 +1,99 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use alloc::collections::VecDeque;
+use core::ops::ControlFlow;
+
+use skykit::TerminationReason;
+
+use crate::system::{
+    tasking::{scheduler::Scheduler, Notification, ThreadState, WaitReason},
+    RegisterState,
+};
+
+/// Makes a notification owned by the current process, its ID is returned in
+/// `rax`.
+pub fn create(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let owner = scheduler.current_pid().unwrap();
+    let id = scheduler.notification_gen.next();
+    scheduler.notifications.insert(
+        id,
+        Notification {
+            owner,
+            bits: 0,
+            waiters: VecDeque::new(),
+        },
+    );
+    state.rax = id;
+    ControlFlow::Continue(())
+}
+
+pub fn destroy(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let id = state.rsi;
+    let Some(notification) = scheduler.notifications.get(&id) else {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    };
+    if Some(notification.owner) != scheduler.current_pid() {
+        return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+    }
+    scheduler.remove_notification(id);
+    ControlFlow::Continue(())
+}
+
+/// Sets bits `rdx` of notification `rsi`, any process may do so.
+pub fn signal(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    if !scheduler.notifications.contains_key(&state.rsi) {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    }
+    scheduler.signal(state.rsi, state.rdx);
+    ControlFlow::Continue(())
+}
+
+/// Takes and clears the bits of notification `rsi`. If none are set, returns
+/// zero right away if `rcx` is non-zero, or waits, for at most `rdx`
+/// milliseconds unless that is zero.
+pub fn wait(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let (id, timeout, non_blocking) = (state.rsi, state.rdx, state.rcx != 0);
+    let pid = scheduler.current_pid();
+    let Some(notification) = scheduler.notifications.get_mut(&id) else {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    };
+    if Some(notification.owner) != pid {
+        return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+    }
+
+    if notification.bits != 0 || non_blocking {
+        let bits = core::mem::take(&mut notification.bits);
+        scheduler.ack_irqs(id, bits);
+        state.rax = bits;
+        return ControlFlow::Continue(());
+    }
+
+    // Stays zero if the wait times out
+    state.rax = 0;
+    let thread = scheduler.current_thread_mut().unwrap();
+    thread.state = ThreadState::Blocked(WaitReason::Notification(id));
+    let tid = thread.id;
+    scheduler
+        .notifications
+        .get_mut(&id)
+        .unwrap()
+        .waiters
+        .push_back(tid);
+    if timeout != 0 {
+        let deadline = scheduler.now().saturating_add(timeout);
+        scheduler.timers.insert(deadline, tid);
+    }
+    ControlFlow::Break(None)
+}
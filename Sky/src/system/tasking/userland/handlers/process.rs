@@ -0,0 +1,35 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use core::ops::ControlFlow;
+
+use skykit::{ExitReason, TerminationReason};
+
+use crate::system::{tasking::scheduler::Scheduler, RegisterState};
+
+/// Ends the current process with exit code `rsi`.
+pub fn quit(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    scheduler.process_teardown(ExitReason::Quit(state.rsi));
+    ControlFlow::Break(None)
+}
+
+/// Has the current process told when PID `rsi` exits. `rax` is zero if
+/// there is no such process.
+pub fn monitor(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let pid = scheduler.current_pid().unwrap();
+    let target = state.rsi;
+    if target == pid {
+        return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+    }
+    let exists = scheduler.processes.contains_key(&target);
+    if exists {
+        scheduler.monitors.entry(target).or_default().insert(pid);
+    }
+    state.rax = exists.into();
+    ControlFlow::Continue(())
+}
@@ -0,0 +1,87 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use alloc::string::String;
+use core::ops::ControlFlow;
+
+use skykit::TerminationReason;
+
+use crate::system::{
+    tasking::{scheduler::Scheduler, ThreadState, WaitReason},
+    RegisterState,
+};
+
+const MAX_NAME_LEN: u64 = 256;
+
+pub fn read_name(scheduler: &Scheduler, addr: u64, size: u64) -> Result<String, TerminationReason> {
+    if size == 0 || size > MAX_NAME_LEN {
+        return Err(TerminationReason::MalformedArgument);
+    }
+    if !scheduler
+        .current_process()
+        .unwrap()
+        .region_is_valid(addr, size)
+    {
+        return Err(TerminationReason::MalformedAddress);
+    }
+    let data = unsafe { core::slice::from_raw_parts(addr as *const u8, size as _) };
+    core::str::from_utf8(data)
+        .map(String::from)
+        .map_err(|_| TerminationReason::MalformedBody)
+}
+
+/// Publishes the current process under the name at `rsi` of `rdx` bytes.
+/// Returns whether it got the name, which stays taken until its owner dies.
+pub fn register(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let name = match read_name(scheduler, state.rsi, state.rdx) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    let pid = scheduler.current_pid().unwrap();
+    if scheduler.services.contains_key(&name) {
+        state.rax = 0;
+        return ControlFlow::Continue(());
+    }
+
+    debug!("PID {pid} registered service {name}");
+    for tid in scheduler.service_waiters.remove(&name).unwrap_or_default() {
+        let Some(thread) = scheduler.threads.get_mut(&tid) else {
+            continue;
+        };
+        thread.regs.rax = pid;
+        thread.state = ThreadState::Inactive;
+        scheduler.wake(tid);
+    }
+    scheduler.services.insert(name, pid);
+    state.rax = 1;
+    ControlFlow::Continue(())
+}
+
+/// Returns the PID behind the name at `rsi` of `rdx` bytes. If nobody has
+/// it yet, returns 0 right away unless `rcx` is non-zero, in which case the
+/// thread waits for it to be registered.
+pub fn lookup(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let name = match read_name(scheduler, state.rsi, state.rdx) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    if let Some(&pid) = scheduler.services.get(&name) {
+        state.rax = pid;
+        return ControlFlow::Continue(());
+    }
+    if state.rcx == 0 {
+        state.rax = 0;
+        return ControlFlow::Continue(());
+    }
+
+    let thread = scheduler.current_thread_mut().unwrap();
+    thread.state = ThreadState::Blocked(WaitReason::Service);
+    let tid = thread.id;
+    scheduler.service_waiters.entry(name).or_default().push(tid);
+    ControlFlow::Break(None)
+}
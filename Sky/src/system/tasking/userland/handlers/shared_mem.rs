@@ -0,0 +1,150 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use core::ops::ControlFlow;
+
+use amd64::paging::PAGE_SIZE;
+use hashbrown::HashMap;
+use skykit::{shared_mem::MAX_SHARED_PAGES, TerminationReason};
+
+use crate::system::{
+    tasking::{scheduler::Scheduler, AllocationType, SharedRegion},
+    RegisterState,
+};
+
+/// Makes a zeroed region of `rsi` pages owned by the current process, its
+/// ID is returned in `rax`. Nothing maps it yet. Returns zero if the size is
+/// over [`MAX_SHARED_PAGES`] or there is not enough memory.
+pub fn create(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let page_count = state.rsi;
+    if page_count == 0 {
+        return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+    }
+    if page_count > MAX_SHARED_PAGES {
+        state.rax = 0;
+        return ControlFlow::Continue(());
+    }
+    let Some(phys) = (unsafe {
+        (*crate::system::state::SYS_STATE.get())
+            .pmm
+            .as_ref()
+            .unwrap()
+            .lock()
+            .alloc(page_count)
+    }) else {
+        state.rax = 0;
+        return ControlFlow::Continue(());
+    };
+    let phys = phys as u64;
+    unsafe {
+        core::ptr::write_bytes(
+            (phys + amd64::paging::PHYS_VIRT_OFFSET) as *mut u8,
+            0,
+            (page_count * PAGE_SIZE) as _,
+        );
+    }
+
+    let id = scheduler.shared_region_gen.next();
+    scheduler.shared_regions.insert(
+        id,
+        SharedRegion {
+            owner: scheduler.current_pid(),
+            addr: phys + skykit::USER_VIRT_OFFSET,
+            page_count,
+            grants: HashMap::new(),
+            refs: 1,
+        },
+    );
+    state.rax = id;
+    ControlFlow::Continue(())
+}
+
+/// Lets the PID `rdx`, or the owner of endpoint `r10` if non-zero, map
+/// region `rsi`, writable if `r8` is non-zero. Granting again replaces the
+/// previous access.
+pub fn grant(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let (id, writable) = (state.rsi, state.r8 != 0);
+    let target = match super::endpoint::resolve(scheduler, state.rdx, state.rcx) {
+        Ok(v) => v,
+        Err(e) => return ControlFlow::Break(Some(e)),
+    };
+    if !scheduler.processes.contains_key(&target) {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    }
+    let pid = scheduler.current_pid();
+    let Some(region) = scheduler.shared_regions.get_mut(&id) else {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    };
+    if region.owner != pid {
+        return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions));
+    }
+    if region.owner != Some(target) {
+        region.grants.insert(target, writable);
+    }
+    ControlFlow::Continue(())
+}
+
+/// Maps region `rsi` into the current process, returns its address in `rax`
+/// and its size in `rdi`.
+pub fn map(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let id = state.rsi;
+    let pid = scheduler.current_pid().unwrap();
+    let Some(region) = scheduler.shared_regions.get_mut(&id) else {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    };
+    let writable = if region.owner == Some(pid) {
+        true
+    } else {
+        match region.grants.get(&pid) {
+            Some(v) => *v,
+            None => return ControlFlow::Break(Some(TerminationReason::InsufficientPermissions)),
+        }
+    };
+    let (addr, size) = (region.addr, region.page_count * PAGE_SIZE);
+
+    let process = scheduler.processes.get_mut(&pid).unwrap();
+    if process.allocations.contains_key(&addr) {
+        return ControlFlow::Break(Some(TerminationReason::AlreadyExists));
+    }
+    process.track_alloc(
+        addr,
+        size,
+        AllocationType::Shared {
+            region: id,
+            writable,
+        },
+    );
+    region.refs += 1;
+    state.rax = addr;
+    state.rdi = size;
+    ControlFlow::Continue(())
+}
+
+/// Unmaps region `rsi` from the current process.
+pub fn unmap(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let id = state.rsi;
+    let Some(addr) = scheduler.shared_regions.get(&id).map(|v| v.addr) else {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    };
+    let process = scheduler.current_process_mut().unwrap();
+    if !matches!(
+        process.allocations.get(&addr),
+        Some((_, AllocationType::Shared { region, .. })) if *region == id
+    ) {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    }
+    process.free_alloc(addr);
+    scheduler.release_shared(id);
+    ControlFlow::Continue(())
+}
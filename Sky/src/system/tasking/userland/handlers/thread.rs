@@ -0,0 +1,81 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use core::ops::ControlFlow;
+
+use skykit::TerminationReason;
+
+use crate::system::{
+    tasking::{scheduler::Scheduler, ThreadState, WaitReason, STACK_SIZE},
+    RegisterState,
+};
+
+pub fn spawn(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let (entry, arg) = (state.rsi, state.rdx);
+    if !scheduler
+        .current_process()
+        .unwrap()
+        .region_is_valid(entry, 1)
+    {
+        return ControlFlow::Break(Some(TerminationReason::MalformedAddress));
+    }
+
+    let priority = scheduler.current_thread_mut().unwrap().priority;
+    let tid = scheduler.tid_gen.next();
+    let process = scheduler.current_process_mut().unwrap();
+    let stack_addr = process.allocate(STACK_SIZE).0;
+    let mut thread = process.new_thread(tid, entry, stack_addr);
+    thread.regs.rdi = arg;
+    // As if the entry point got `call`ed
+    thread.regs.rsp -= 8;
+    thread.priority = priority;
+    thread.effective_priority = priority;
+    scheduler.threads.try_insert(tid, thread).unwrap();
+    scheduler.wake(tid);
+
+    state.rax = tid;
+    ControlFlow::Continue(())
+}
+
+pub fn join(
+    scheduler: &mut Scheduler,
+    state: &mut RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let tid = state.rsi;
+    if scheduler.current_tid() == Some(tid) {
+        return ControlFlow::Break(Some(TerminationReason::MalformedArgument));
+    }
+
+    let process = scheduler.current_process_mut().unwrap();
+    if let Some(code) = process.exited_threads.remove(&tid) {
+        scheduler.tid_gen.free(tid);
+        state.rax = code;
+        return ControlFlow::Continue(());
+    }
+    if !process.thread_ids.contains(&tid) {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    }
+
+    scheduler.current_thread_mut().unwrap().state = ThreadState::Blocked(WaitReason::Join(tid));
+    ControlFlow::Break(None)
+}
+
+/// Gives up on joining thread `rsi`, its ID gets freed once it exits.
+pub fn detach(
+    scheduler: &mut Scheduler,
+    state: &RegisterState,
+) -> ControlFlow<Option<TerminationReason>> {
+    let tid = state.rsi;
+    let process = scheduler.current_process_mut().unwrap();
+    if process.exited_threads.remove(&tid).is_some() {
+        scheduler.tid_gen.free(tid);
+        return ControlFlow::Continue(());
+    }
+    if !process.thread_ids.contains(&tid) {
+        return ControlFlow::Break(Some(TerminationReason::NotFound));
+    }
+    process.detached_threads.insert(tid);
+    ControlFlow::Continue(())
+}
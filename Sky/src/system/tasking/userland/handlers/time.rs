@@ -0,0 +1,17 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use core::ops::ControlFlow;
+
+use skykit::TerminationReason;
+
+use crate::system::RegisterState;
+
+pub fn monotonic(state: &mut RegisterState) -> ControlFlow<Option<TerminationReason>> {
+    state.rax = crate::timer::monotonic_ns();
+    ControlFlow::Continue(())
+}
+
+pub fn wall_clock(state: &mut RegisterState) -> ControlFlow<Option<TerminationReason>> {
+    state.rax = crate::timer::wall_clock_ns();
+    ControlFlow::Continue(())
+}
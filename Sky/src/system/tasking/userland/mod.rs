@@ -2,16 +2,28 @@
 
 use core::ops::ControlFlow;
 
-use skykit::{syscall::SystemCall, TerminationReason};
+use amd64::msr::{
+    efer::ExtendedFeatureEnableReg,
+    syscall::{LStar, SfMask, Star},
+    ModelSpecificReg,
+};
+use skykit::{syscall::SystemCall, ExitReason, TerminationReason};
 
-use crate::system::{gdt::PrivilegeLevel, RegisterState};
+use crate::system::{
+    gdt::{PrivilegeLevel, SegmentSelector},
+    percpu::PerCpu,
+    RegisterState,
+};
 
 pub mod handlers;
 pub mod page_table;
 
 unsafe extern "sysv64" fn syscall_handler(state: &mut RegisterState) {
-    let sys_state = &mut *crate::system::state::SYS_STATE.get();
-    let mut scheduler = sys_state.scheduler.as_ref().unwrap().lock();
+    let mut scheduler = super::scheduler::lock();
+    if scheduler.current_dying() {
+        scheduler.schedule(state);
+        return;
+    }
 
     let flow = 'flow: {
         let Ok(v) = SystemCall::try_from(state.rdi) else {
@@ -22,7 +34,7 @@ unsafe extern "sysv64" fn syscall_handler(state: &mut RegisterState) {
             SystemCall::KPrint => handlers::kprint(&scheduler, state),
             SystemCall::MsgRecv => handlers::msg::recv(&mut scheduler, state),
             SystemCall::MsgSend => handlers::msg::send(&mut scheduler, state),
-            SystemCall::Quit => scheduler.thread_teardown(),
+            SystemCall::Quit => handlers::process::quit(&mut scheduler, state),
             SystemCall::Yield => ControlFlow::Break(None),
             SystemCall::PortIn => handlers::port::port_in(state),
             SystemCall::PortOut => handlers::port::port_out(state),
@@ -33,6 +45,40 @@ unsafe extern "sysv64" fn syscall_handler(state: &mut RegisterState) {
             SystemCall::NewOSDTEntry => handlers::os_dt_entry::new_entry(state),
             SystemCall::GetOSDTEntryInfo => handlers::os_dt_entry::get_info(&mut scheduler, state),
             SystemCall::SetOSDTEntryProp => handlers::os_dt_entry::set_prop(&mut scheduler, state),
+            SystemCall::SetPriority => scheduler.set_priority(state),
+            SystemCall::SpawnThread => handlers::thread::spawn(&mut scheduler, state),
+            SystemCall::ExitThread => scheduler.thread_teardown(state.rsi),
+            SystemCall::JoinThread => handlers::thread::join(&mut scheduler, state),
+            SystemCall::Sleep => scheduler.sleep(state),
+            SystemCall::GetMonotonicTime => handlers::time::monotonic(state),
+            SystemCall::GetWallClock => handlers::time::wall_clock(state),
+            SystemCall::FutexWait => handlers::futex::wait(&mut scheduler, state),
+            SystemCall::FutexWake => handlers::futex::wake(&mut scheduler, state),
+            SystemCall::MsgCall => handlers::msg::call(&mut scheduler, state),
+            SystemCall::MsgReply => handlers::msg::reply(&mut scheduler, state),
+            SystemCall::RegisterService => handlers::service::register(&mut scheduler, state),
+            SystemCall::LookupService => handlers::service::lookup(&mut scheduler, state),
+            SystemCall::CreateEndpoint => handlers::endpoint::create(&mut scheduler, state),
+            SystemCall::DestroyEndpoint => handlers::endpoint::destroy(&mut scheduler, state),
+            SystemCall::CreateNotification => handlers::notification::create(&mut scheduler, state),
+            SystemCall::DestroyNotification => {
+                handlers::notification::destroy(&mut scheduler, state)
+            }
+            SystemCall::Signal => handlers::notification::signal(&mut scheduler, state),
+            SystemCall::WaitNotification => handlers::notification::wait(&mut scheduler, state),
+            SystemCall::BindIRQ => scheduler.bind_irq(state),
+            SystemCall::CreateSharedRegion => handlers::shared_mem::create(&mut scheduler, state),
+            SystemCall::GrantSharedRegion => handlers::shared_mem::grant(&mut scheduler, state),
+            SystemCall::MapSharedRegion => handlers::shared_mem::map(&mut scheduler, state),
+            SystemCall::UnmapSharedRegion => handlers::shared_mem::unmap(&mut scheduler, state),
+            SystemCall::SetQueueLimit => handlers::msg::set_queue_limit(&mut scheduler, state),
+            SystemCall::GetQueueStats => handlers::msg::queue_stats(&scheduler, state),
+            SystemCall::CreateChannel => handlers::channel::create(&mut scheduler, state),
+            SystemCall::SubscribeChannel => handlers::channel::subscribe(&mut scheduler, state),
+            SystemCall::PublishChannel => handlers::channel::publish(&mut scheduler, state),
+            SystemCall::MonitorProcess => handlers::process::monitor(&mut scheduler, state),
+            SystemCall::UnregisterIRQ => scheduler.unregister_irq(state),
+            SystemCall::DetachThread => handlers::thread::detach(&mut scheduler, state),
         }
     };
 
@@ -43,13 +89,115 @@ unsafe extern "sysv64" fn syscall_handler(state: &mut RegisterState) {
     if let Some(reason) = reason {
         debug!(
             "PID {} performed illegal action (<{reason:?}>). Killing it, good riddance.",
-            scheduler.current_pid.unwrap()
+            scheduler.current_pid().unwrap()
         );
-        scheduler.process_teardown();
+        scheduler.process_teardown(ExitReason::Killed(reason));
     }
     scheduler.schedule(state);
 }
 
+/// Stored in [`RegisterState::int_num`] for threads that entered through
+/// `syscall`. Such threads expect RCX and R11 to be clobbered, so they can
+/// be resumed with `sysret`.
+const SYSCALL_INT_NUM: u64 = 0x100;
+
+/// Whether the thread entered through `syscall`. The `int 249` gate keeps
+/// the original ABI, arguments that existing calls gained since are only
+/// read on this path.
+#[inline]
+pub const fn is_syscall(state: &RegisterState) -> bool {
+    state.int_num == SYSCALL_INT_NUM
+}
+
+/// Builds the same frame as the `int 249` gate so [`syscall_handler`] can be
+/// shared. The fourth argument comes in R10 as `syscall` clobbers RCX.
+#[naked]
+unsafe extern "sysv64" fn syscall_entry() {
+    core::arch::naked_asm!(
+        "swapgs",
+        "mov gs:[{user_rsp}], rsp",
+        "mov rsp, gs:[{kern_rsp}]",
+        "push {user_ss}",
+        "push gs:[{user_rsp}]",
+        "push r11",
+        "push {user_cs}",
+        "push rcx",
+        "push 0",
+        "push {int_num}",
+        "push rax",
+        "push rbx",
+        "push r10",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        "call {handler}",
+        // The handler may have switched to a thread that did not come
+        // through here, that one needs the full `iretq` path.
+        "cmp qword ptr [rsp + 15 * 8], {int_num}",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "lea rsp, [rsp + 16]",
+        "jne 2f",
+        "mov rcx, [rsp]",
+        "mov r11, [rsp + 16]",
+        "mov rsp, [rsp + 24]",
+        "swapgs",
+        "sysretq",
+        "2:",
+        "test qword ptr [rsp + 8], 3",
+        "jz 3f",
+        "swapgs",
+        "3:",
+        "iretq",
+        user_rsp = const core::mem::offset_of!(PerCpu, user_rsp),
+        kern_rsp = const core::mem::offset_of!(PerCpu, kern_rsp),
+        user_ss = const SegmentSelector::new(3, PrivilegeLevel::User).0,
+        user_cs = const SegmentSelector::new(4, PrivilegeLevel::User).0,
+        int_num = const SYSCALL_INT_NUM,
+        handler = sym syscall_handler,
+    )
+}
+
+/// Enables `syscall` on the calling core.
+pub fn init_core() {
+    unsafe {
+        ExtendedFeatureEnableReg::read()
+            .with_syscall_ext(true)
+            .write();
+        Star::new()
+            .with_syscall_cs(SegmentSelector::new(1, PrivilegeLevel::Supervisor).0)
+            .with_sysret_cs(SegmentSelector::new(2, PrivilegeLevel::User).0)
+            .write();
+        LStar::new().with_rip(syscall_entry as usize as u64).write();
+        // IF, TF, DF, AC
+        SfMask::new().with_mask(0x4_0700).write();
+    }
+}
+
 pub fn setup() {
     crate::interrupts::idt::set_handler(249, 1, PrivilegeLevel::User, syscall_handler, false, true);
+    init_core();
 }
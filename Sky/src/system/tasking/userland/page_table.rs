@@ -43,6 +43,11 @@ impl UserPML4 {
             .map(&move || Self::alloc_entry(pid), virt, phys, count, flags);
     }
 
+    #[inline]
+    pub unsafe fn virt_to_phys(&mut self, virt: u64) -> Option<(u64, PageTableFlags)> {
+        self.0.virt_to_phys(virt)
+    }
+
     #[inline]
     pub unsafe fn unmap(&mut self, virt: u64, count: u64) {
         self.0.unmap(virt, count);
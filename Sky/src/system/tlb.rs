@@ -0,0 +1,77 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    acpi::apic::InterruptCommand,
+    system::{gdt::PrivilegeLevel, percpu::PerCpu, RegisterState},
+};
+
+pub const SHOOTDOWN_VECTOR: u8 = 129;
+
+/// Flushes asked of each core so far, by LAPIC ID.
+static REQUESTED: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+/// Flushes each core got done so far. Only ever written by that core.
+static DONE: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+unsafe extern "sysv64" fn shootdown_handler(_state: &mut RegisterState) {
+    serve();
+}
+
+pub fn init() {
+    crate::interrupts::idt::set_handler(
+        SHOOTDOWN_VECTOR,
+        1,
+        PrivilegeLevel::Supervisor,
+        shootdown_handler,
+        true,
+        true,
+    );
+}
+
+/// Flushes the TLB of the calling core if another core asked for it since
+/// the last time.
+pub fn serve() {
+    let id = usize::from(PerCpu::current().id);
+    let requested = REQUESTED[id].load(Ordering::Acquire);
+    if DONE[id].load(Ordering::Relaxed) >= requested {
+        return;
+    }
+    unsafe {
+        core::arch::asm!(
+            "mov {0}, cr3",
+            "mov cr3, {0}",
+            out(reg) _,
+            options(nostack, preserves_flags)
+        );
+    }
+    DONE[id].store(requested, Ordering::Release);
+}
+
+/// Makes every core in `cores` but the calling one flush its TLB and waits
+/// until they did. The calling core has to invalidate its own entries.
+///
+/// Cores spinning on the scheduler lock serve the request from there, so
+/// the caller may hold it.
+pub fn shootdown(cores: impl IntoIterator<Item = u8>) {
+    let cpu = PerCpu::current();
+    let pending: Vec<_> = cores
+        .into_iter()
+        .filter(|&v| v != cpu.id)
+        .map(|core| {
+            let ticket = REQUESTED[usize::from(core)].fetch_add(1, Ordering::AcqRel) + 1;
+            cpu.lapic.send_ipi(
+                InterruptCommand::new()
+                    .with_vector(SHOOTDOWN_VECTOR)
+                    .with_dest(core),
+            );
+            (core, ticket)
+        })
+        .collect();
+    for (core, ticket) in pending {
+        while DONE[usize::from(core)].load(Ordering::Acquire) < ticket {
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -38,6 +38,10 @@ impl PageTableLvl4 {
         self.0.map(&Self::alloc_entry, virt, phys, count, flags);
     }
 
+    pub unsafe fn unmap(&mut self, virt: u64, count: u64) {
+        self.0.unmap(virt, count);
+    }
+
     pub unsafe fn map_higher_half(&mut self) {
         self.0.map_higher_half(&Self::alloc_entry);
     }
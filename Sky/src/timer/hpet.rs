@@ -1,7 +1,19 @@
 // Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
 
-use crate::acpi::tables::hpet::{regs::GeneralConfiguration, Hpet as HpetInner};
+use core::sync::atomic::{AtomicU32, Ordering};
 
+use crate::acpi::{
+    ioapic::find_for_gsi,
+    tables::{
+        hpet::{regs::GeneralConfiguration, Hpet as HpetInner},
+        madt::ic::ioapic::IOAPICRedir,
+    },
+};
+
+/// Comparators handed out by [`Hpet::alloc_comparator`], one bit each.
+static ALLOCATED: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy)]
 pub struct Hpet {
     inner: &'static HpetInner,
     clk: u64,
@@ -16,6 +28,180 @@ impl Hpet {
         hpet.set_config(GeneralConfiguration::new().with_main_cnt_enable(true));
         Self { inner: hpet, clk }
     }
+
+    /// Whether the main counter is 64 bits wide. A 32-bit one wraps within
+    /// minutes.
+    pub fn is_64bit(&self) -> bool {
+        self.inner.capabilities().main_cnt_64bit()
+    }
+
+    fn ns_to_ticks(&self, ns: u64) -> u64 {
+        (u128::from(ns) * 1_000_000 / u128::from(self.clk)) as u64
+    }
+
+    /// Claims a free comparator and routes it to `vector` on the LAPIC with
+    /// ID `apic_id`, over the FSB if the comparator supports it or through
+    /// an I/O APIC input otherwise. With `periodic` only comparators that
+    /// can run periodically are considered.
+    pub fn alloc_comparator(&self, vector: u8, apic_id: u8, periodic: bool) -> Option<Comparator> {
+        // `num_timers` holds the index of the last comparator
+        for index in 0..=self.inner.capabilities().num_timers() {
+            let bit = 1 << index;
+            let cfg = self.inner.timer_config(index);
+            if (periodic && !cfg.periodic_supported())
+                || ALLOCATED.fetch_or(bit, Ordering::AcqRel) & bit != 0
+            {
+                continue;
+            }
+
+            let cfg = cfg
+                .with_intr_enable(false)
+                .with_periodic(false)
+                .with_level_triggered(false)
+                .with_force_32bit(false);
+            let delivery = if cfg.fsb_intr_supported() {
+                self.inner.set_timer_fsb_route(
+                    index,
+                    0xFEE0_0000 | (u32::from(apic_id) << 12),
+                    u32::from(vector),
+                );
+                self.inner.set_timer_config(index, cfg.with_fsb_intr(true));
+                Delivery::Fsb
+            } else if let Some(gsi) = route_to_ioapic(cfg.intr_route(), vector, apic_id) {
+                self.inner.set_timer_config(
+                    index,
+                    cfg.with_fsb_intr(false).with_ioapic_intr_route(gsi as u8),
+                );
+                Delivery::IoApic(gsi)
+            } else {
+                ALLOCATED.fetch_and(!bit, Ordering::AcqRel);
+                continue;
+            };
+
+            trace!("HPET comparator {index} routed via {delivery:?}");
+            return Some(Comparator {
+                hpet: *self,
+                index,
+                delivery,
+            });
+        }
+        None
+    }
+}
+
+/// Programs the highest I/O APIC input out of `allowed` that isn't an ISA
+/// IRQ and isn't in use yet.
+fn route_to_ioapic(allowed: u32, vector: u8, apic_id: u8) -> Option<u32> {
+    let state = unsafe { &*crate::system::state::SYS_STATE.get() };
+    let madt = state.madt.as_ref().unwrap().lock();
+    (16..32)
+        .rev()
+        .filter(|gsi| allowed & (1 << gsi) != 0 && !madt.isos.iter().any(|v| v.gsi == *gsi))
+        .find_map(|gsi| {
+            let ioapic = find_for_gsi(&madt, gsi)?;
+            let pin = gsi - ioapic.gsi_base;
+            // Unused inputs stay masked from reset
+            if !ioapic.read_redir(pin).masked() {
+                return None;
+            }
+            ioapic.write_redir(
+                pin,
+                IOAPICRedir::new().with_vector(vector).with_dest(apic_id),
+            );
+            Some(gsi)
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// MSI straight to the LAPIC.
+    Fsb,
+    /// Through this I/O APIC input.
+    IoApic(u32),
+}
+
+/// An HPET comparator acting as a clock event device. Stopped and released
+/// on drop.
+pub struct Comparator {
+    hpet: Hpet,
+    index: u8,
+    delivery: Delivery,
+}
+
+impl Comparator {
+    /// Most ticks ahead the comparator can be set, 32-bit ones only match
+    /// the low half of the main counter.
+    fn max_ticks(&self) -> u64 {
+        if self.hpet.inner.timer_config(self.index).is_64_bit() {
+            u64::MAX >> 1
+        } else {
+            u64::from(u32::MAX >> 1)
+        }
+    }
+
+    /// Fires once, `ns` nanoseconds from now or as soon as possible. Longer
+    /// than the comparator can count fires early.
+    pub fn one_shot(&self, ns: u64) {
+        let inner = self.hpet.inner;
+        let cfg = inner.timer_config(self.index);
+        inner.set_timer_config(self.index, cfg.with_periodic(false).with_intr_enable(true));
+        // Comparators only match on equality, a deadline the counter passed
+        // while it was being written would be missed by a whole wrap
+        let max = self.max_ticks();
+        let mut ticks = self.hpet.ns_to_ticks(ns).clamp(1, max);
+        loop {
+            let target = inner.counter_value().wrapping_add(ticks);
+            inner.set_timer_comparator(self.index, target);
+            if (inner.counter_value().wrapping_sub(target) as i64) < 0 {
+                break;
+            }
+            ticks = (ticks * 2).min(max);
+        }
+    }
+
+    /// Fires every `ns` nanoseconds. Returns `false` if the comparator can
+    /// only do one-shots.
+    #[allow(dead_code)]
+    pub fn periodic(&self, ns: u64) -> bool {
+        let inner = self.hpet.inner;
+        let cfg = inner.timer_config(self.index);
+        if !cfg.periodic_supported() {
+            return false;
+        }
+
+        let ticks = self.hpet.ns_to_ticks(ns).clamp(1, self.max_ticks());
+        // With the accumulator bit set, the first write sets the next
+        // deadline and the second one the period
+        inner.set_timer_config(
+            self.index,
+            cfg.with_periodic(true)
+                .with_timer_accumulator(true)
+                .with_intr_enable(true),
+        );
+        inner.set_timer_comparator(self.index, inner.counter_value() + ticks);
+        inner.set_timer_comparator(self.index, ticks);
+        true
+    }
+
+    pub fn stop(&self) {
+        let inner = self.hpet.inner;
+        let cfg = inner.timer_config(self.index);
+        inner.set_timer_config(self.index, cfg.with_intr_enable(false).with_periodic(false));
+    }
+}
+
+impl Drop for Comparator {
+    fn drop(&mut self) {
+        self.stop();
+        if let Delivery::IoApic(gsi) = self.delivery {
+            let state = unsafe { &*crate::system::state::SYS_STATE.get() };
+            let madt = state.madt.as_ref().unwrap().lock();
+            if let Some(ioapic) = find_for_gsi(&madt, gsi) {
+                ioapic.write_redir(gsi - ioapic.gsi_base, IOAPICRedir::new().with_masked(true));
+            }
+        }
+        ALLOCATED.fetch_and(!(1 << self.index), Ordering::AcqRel);
+    }
 }
 
 impl super::Timer for Hpet {
@@ -29,3 +215,14 @@ impl super::Timer for Hpet {
         }
     }
 }
+
+/// Only used with a 64-bit main counter, which takes centuries to wrap.
+impl super::ClockSource for Hpet {
+    fn name(&self) -> &'static str {
+        "HPET"
+    }
+
+    fn nanos(&self) -> u64 {
+        (u128::from(self.inner.counter_value()) * u128::from(self.clk) / 1_000_000) as u64
+    }
+}
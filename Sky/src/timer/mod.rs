@@ -1,8 +1,145 @@
 // Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
 
+use alloc::boxed::Box;
+
 pub mod hpet;
 pub mod pit;
+pub mod rtc;
+pub mod tsc;
 
 pub trait Timer {
     fn sleep(&self, ms: u64);
 }
+
+/// A free-running counter the kernel keeps monotonic time with.
+pub trait ClockSource: Sync {
+    fn name(&self) -> &'static str;
+    /// Nanoseconds since the source was set up.
+    fn nanos(&self) -> u64;
+    /// Like [`Self::nanos`], but never waits on a lock and may hand out the
+    /// last time read instead. For the logger, which can run while the
+    /// interrupted code holds one.
+    fn peek_nanos(&self) -> u64 {
+        self.nanos()
+    }
+    /// Longest the source may go unread before it loses time, if it wraps
+    /// without anyone noticing.
+    fn max_idle_ns(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// The device used for LAPIC calibration and busy-wait sleeps.
+pub enum SystemTimer {
+    Hpet(hpet::Hpet),
+    Tsc(tsc::Tsc),
+    Pit(pit::Pit),
+}
+
+impl SystemTimer {
+    /// Picks the best timer available: the HPET, then the TSC if CPUID reports
+    /// its frequency, then the PIT which every PC has.
+    pub fn select(state: &crate::system::state::SystemState) -> Self {
+        let this = crate::acpi::get_hpet(state)
+            .map(Self::Hpet)
+            .or_else(|| tsc::Tsc::from_cpuid().map(Self::Tsc))
+            .unwrap_or_else(|| Self::Pit(pit::Pit::new()));
+        info!("Using the {} as timer", this.name());
+        set_root_prop(state, "Timer", this.name());
+        this
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Hpet(_) => "HPET",
+            Self::Tsc(_) => "TSC",
+            Self::Pit(_) => "PIT",
+        }
+    }
+}
+
+impl Timer for SystemTimer {
+    fn sleep(&self, ms: u64) {
+        match self {
+            Self::Hpet(v) => v.sleep(ms),
+            Self::Tsc(v) => v.sleep(ms),
+            Self::Pit(v) => v.sleep(ms),
+        }
+    }
+}
+
+fn set_root_prop(state: &crate::system::state::SystemState, key: &str, value: &str) {
+    state
+        .dt_index
+        .as_ref()
+        .unwrap()
+        .read()
+        .get(&0)
+        .unwrap()
+        .lock()
+        .properties
+        .insert(key.into(), value.into());
+}
+
+/// Picks the best clock source available: the invariant TSC, calibrated
+/// against `timer` unless CPUID gave its frequency, then the HPET main
+/// counter if it is 64 bits wide, then the PIT.
+pub fn init_clocksource(state: &mut crate::system::state::SystemState, timer: &SystemTimer) {
+    let source: Box<dyn ClockSource> = match timer {
+        SystemTimer::Tsc(tsc) => Box::new(*tsc),
+        SystemTimer::Hpet(hpet) => tsc::Tsc::calibrate(hpet).map_or_else(
+            || {
+                if hpet.is_64bit() {
+                    Box::new(*hpet) as Box<dyn ClockSource>
+                } else {
+                    Box::new(pit::Pit::new())
+                }
+            },
+            |v| Box::new(v),
+        ),
+        SystemTimer::Pit(pit) => tsc::Tsc::calibrate(pit).map_or_else(
+            || Box::new(pit::Pit::new()) as Box<dyn ClockSource>,
+            |v| Box::new(v),
+        ),
+    };
+    info!("Using the {} as clock source", source.name());
+    set_root_prop(state, "ClockSource", source.name());
+    state.clocksource = Some(source);
+}
+
+/// Nanoseconds since the clock source was set up, zero before that.
+pub fn monotonic_ns() -> u64 {
+    unsafe {
+        (*crate::system::state::SYS_STATE.get())
+            .clocksource
+            .as_ref()
+    }
+    .map_or(0, |v| v.nanos())
+}
+
+/// See [`ClockSource::peek_nanos`].
+pub fn peek_monotonic_ns() -> u64 {
+    unsafe {
+        (*crate::system::state::SYS_STATE.get())
+            .clocksource
+            .as_ref()
+    }
+    .map_or(0, |v| v.peek_nanos())
+}
+
+/// See [`ClockSource::max_idle_ns`].
+pub fn max_idle_ns() -> Option<u64> {
+    unsafe {
+        (*crate::system::state::SYS_STATE.get())
+            .clocksource
+            .as_ref()
+    }
+    .and_then(|v| v.max_idle_ns())
+}
+
+/// Nanoseconds since the Unix epoch, as read from the RTC at boot and kept
+/// going by the clock source.
+pub fn wall_clock_ns() -> u64 {
+    let offset = unsafe { (*crate::system::state::SYS_STATE.get()).wall_clock_offset };
+    offset + monotonic_ns()
+}
@@ -1,7 +1,12 @@
 // Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
 
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use amd64::io::port::Port;
 
+/// Oscillator frequency in Hz.
+const FREQUENCY: u64 = 1_193_182;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 /// 3 bits
@@ -121,6 +126,19 @@ impl ProgrammableIntervalTimer {
         self
     }
 
+    pub fn with_rate_generator(self) -> Self {
+        unsafe {
+            self.mode_cmd.write(
+                ModeCommand::new()
+                    .with_bcd(false)
+                    .with_mode(Mode::RateGenerator)
+                    .with_access_mode(AccessMode::LoByteOrHiByte)
+                    .with_channel(Channel::Zero),
+            );
+        }
+        self
+    }
+
     pub fn read_counter(self) -> u16 {
         unsafe {
             self.mode_cmd.write(ModeCommand::new());
@@ -143,3 +161,86 @@ impl ProgrammableIntervalTimer {
         self
     }
 }
+
+/// Serialises latch commands, the count is read in two halves.
+static LATCH: spin::Mutex<()> = spin::Mutex::new(());
+
+/// Channel 0 counting down over the full 16-bit range, wrapping about every
+/// 55 milliseconds.
+pub struct Pit {
+    inner: ProgrammableIntervalTimer,
+    /// Last count read and the ticks accumulated up to it.
+    count: spin::Mutex<(u16, u64)>,
+    /// Nanoseconds last handed out.
+    last_ns: AtomicU64,
+}
+
+impl Pit {
+    pub fn new() -> Self {
+        let inner = ProgrammableIntervalTimer::new()
+            .with_rate_generator()
+            .with_reload(0);
+        Self {
+            inner,
+            count: spin::Mutex::new((Self::read(inner), 0)),
+            last_ns: AtomicU64::new(0),
+        }
+    }
+
+    fn read(inner: ProgrammableIntervalTimer) -> u16 {
+        let _guard = LATCH.lock();
+        inner.read_counter()
+    }
+
+    /// Accounts for the ticks since the last read, which left `count`.
+    fn advance(&self, state: &mut (u16, u64), count: u16) -> u64 {
+        state.1 += u64::from(state.0.wrapping_sub(count));
+        state.0 = count;
+        let ns = (u128::from(state.1) * 1_000_000_000 / u128::from(FREQUENCY)) as u64;
+        self.last_ns.store(ns, Ordering::Relaxed);
+        ns
+    }
+}
+
+impl super::Timer for Pit {
+    fn sleep(&self, ms: u64) {
+        let target = ms * FREQUENCY / 1000;
+        let mut last = Self::read(self.inner);
+        let mut elapsed = 0;
+
+        while elapsed < target {
+            let count = Self::read(self.inner);
+            elapsed += u64::from(last.wrapping_sub(count));
+            last = count;
+            unsafe {
+                core::arch::x86_64::_mm_pause();
+            }
+        }
+    }
+}
+
+impl super::ClockSource for Pit {
+    fn name(&self) -> &'static str {
+        "PIT"
+    }
+
+    fn nanos(&self) -> u64 {
+        let mut state = self.count.lock();
+        let count = Self::read(self.inner);
+        self.advance(&mut state, count)
+    }
+
+    fn peek_nanos(&self) -> u64 {
+        let Some(mut state) = self.count.try_lock() else {
+            return self.last_ns.load(Ordering::Relaxed);
+        };
+        let Some(count) = LATCH.try_lock().map(|_guard| self.inner.read_counter()) else {
+            return self.last_ns.load(Ordering::Relaxed);
+        };
+        self.advance(&mut state, count)
+    }
+
+    fn max_idle_ns(&self) -> Option<u64> {
+        Some(50_000_000)
+    }
+}
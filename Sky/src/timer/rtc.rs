@@ -0,0 +1,214 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use amd64::io::port::Port;
+
+/// Serialises index and data accesses on the CMOS ports.
+static CMOS: spin::Mutex<Cmos> = spin::Mutex::new(Cmos::new());
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+enum CmosReg {
+    Seconds = 0x00,
+    Minutes = 0x02,
+    Hours = 0x04,
+    DayOfMonth = 0x07,
+    Month = 0x08,
+    Year = 0x09,
+    StatusA = 0x0A,
+    StatusB = 0x0B,
+}
+
+#[bitfield(u8)]
+struct StatusA {
+    #[bits(4)]
+    rate: u8,
+    #[bits(3)]
+    divider: u8,
+    update_in_progress: bool,
+}
+
+#[bitfield(u8)]
+struct StatusB {
+    daylight_savings: bool,
+    hour_24: bool,
+    binary: bool,
+    square_wave: bool,
+    update_ended_intr: bool,
+    alarm_intr: bool,
+    periodic_intr: bool,
+    set: bool,
+}
+
+struct Cmos {
+    index: Port<u8, u8>,
+    data: Port<u8, u8>,
+}
+
+impl Cmos {
+    const fn new() -> Self {
+        Self {
+            index: Port::new(0x70),
+            data: Port::new(0x71),
+        }
+    }
+
+    fn read(&self, reg: u8) -> u8 {
+        unsafe {
+            self.index.write(reg);
+            self.data.read()
+        }
+    }
+
+    fn update_in_progress(&self) -> bool {
+        StatusA::from(self.read(CmosReg::StatusA as u8)).update_in_progress()
+    }
+
+    /// Raw register contents, in whatever format the RTC is configured for.
+    fn read_raw(&self, century_reg: Option<u8>) -> [u8; 7] {
+        while self.update_in_progress() {
+            core::hint::spin_loop();
+        }
+        [
+            self.read(CmosReg::Seconds as u8),
+            self.read(CmosReg::Minutes as u8),
+            self.read(CmosReg::Hours as u8),
+            self.read(CmosReg::DayOfMonth as u8),
+            self.read(CmosReg::Month as u8),
+            self.read(CmosReg::Year as u8),
+            century_reg.map_or(0, |v| self.read(v)),
+        ]
+    }
+}
+
+const fn from_bcd(v: u8) -> u8 {
+    (v >> 4) * 10 + (v & 0xF)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Reads the RTC, retrying until two reads in a row agree so an update
+    /// cycle can't tear the result.
+    pub fn read_rtc(century_reg: Option<u8>) -> Self {
+        let cmos = CMOS.lock();
+        let mut raw = cmos.read_raw(century_reg);
+        loop {
+            let next = cmos.read_raw(century_reg);
+            if next == raw {
+                break;
+            }
+            raw = next;
+        }
+        let status = StatusB::from(cmos.read(CmosReg::StatusB as u8));
+        drop(cmos);
+
+        let [mut second, mut minute, mut hour, mut day, mut month, mut year, mut century] = raw;
+        let pm = hour & 0x80 != 0;
+        hour &= 0x7F;
+        if !status.binary() {
+            second = from_bcd(second);
+            minute = from_bcd(minute);
+            hour = from_bcd(hour);
+            day = from_bcd(day);
+            month = from_bcd(month);
+            year = from_bcd(year);
+            century = from_bcd(century);
+        }
+        if !status.hour_24() {
+            // 12 AM is midnight and 12 PM is noon
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+
+        let year = if century_reg.is_some() {
+            u16::from(century) * 100 + u16::from(year)
+        } else {
+            // No century register, assume the 21st century
+            2000 + u16::from(year)
+        };
+
+        Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    /// Seconds since the Unix epoch.
+    pub fn to_unix(self) -> u64 {
+        // Days from civil, proleptic Gregorian with March-based years
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let month = i64::from(self.month);
+        let doy =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        (days * 86400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second))
+        .max(0) as u64
+    }
+
+    pub fn from_unix(secs: u64) -> Self {
+        // Civil from days, the inverse of `to_unix`
+        let days = (secs / 86400) as i64 + 719_468;
+        let secs = secs % 86400;
+        let era = days.div_euclid(146_097);
+        let doe = days - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + i64::from(month <= 2)) as u16;
+
+        Self {
+            year,
+            month,
+            day,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+}
+
+impl core::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Reads the RTC once and anchors the wall clock to the monotonic clock.
+pub fn init_wall_clock(state: &mut crate::system::state::SystemState) {
+    let century_reg = state
+        .acpi
+        .as_ref()
+        .unwrap()
+        .find::<crate::acpi::tables::fadt::FixedACPIDescTable>("FACP")
+        .and_then(|v| v.century_reg());
+    let now = DateTime::read_rtc(century_reg);
+    info!("RTC time is {now}");
+    state.wall_clock_offset = (now.to_unix() * 1_000_000_000).saturating_sub(super::monotonic_ns());
+}
@@ -0,0 +1,91 @@
+// Copyright (c) ChefKiss 2021-2024. Licensed under the Thou Shalt Not Profit License version 1.5. See LICENSE for details.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use amd64::cpuid::CPUIdentification;
+
+/// In milliseconds.
+const CALIBRATION_PERIOD: u64 = 50;
+
+/// Latest time handed out. The TSCs of the cores can be slightly apart, and
+/// time must not go backwards for a thread moving between them.
+static LAST_NS: AtomicU64 = AtomicU64::new(0);
+
+/// The time stamp counter, calibrated once against a reference [`super::Timer`]
+/// or going by the frequency CPUID reports.
+#[derive(Clone, Copy)]
+pub struct Tsc {
+    base: u64,
+    /// Ticks counted over [`CALIBRATION_PERIOD`].
+    ticks: u64,
+}
+
+impl Tsc {
+    /// Returns `None` if the TSC rate changes with power states, in which
+    /// case it is useless for keeping time.
+    pub fn calibrate(timer: &impl super::Timer) -> Option<Self> {
+        if !CPUIdentification::new().invariant_tsc {
+            return None;
+        }
+
+        let base = unsafe { core::arch::x86_64::_rdtsc() };
+        timer.sleep(CALIBRATION_PERIOD);
+        let ticks = unsafe { core::arch::x86_64::_rdtsc() } - base;
+        debug!("TSC runs at {} kHz", ticks / CALIBRATION_PERIOD);
+        Some(Self { base, ticks })
+    }
+
+    /// Skips calibration if the CPU reports the TSC frequency, which only
+    /// recent Intel parts do.
+    pub fn from_cpuid() -> Option<Self> {
+        let cpuid = CPUIdentification::new();
+        if !cpuid.invariant_tsc {
+            return None;
+        }
+
+        let leaf15 = (cpuid.largest_func_id >= 0x15)
+            .then(|| unsafe { core::arch::x86_64::__cpuid(0x15) })
+            .filter(|v| v.eax != 0 && v.ebx != 0)?;
+        let hz = if leaf15.ecx == 0 {
+            // No crystal frequency, the processor base frequency is close enough
+            let leaf16 = (cpuid.largest_func_id >= 0x16)
+                .then(|| unsafe { core::arch::x86_64::__cpuid(0x16) })
+                .filter(|v| v.eax != 0)?;
+            u64::from(leaf16.eax) * 1_000_000
+        } else {
+            u64::from(leaf15.ecx) * u64::from(leaf15.ebx) / u64::from(leaf15.eax)
+        };
+        debug!("CPUID reports a {} kHz TSC", hz / 1000);
+
+        Some(Self {
+            base: unsafe { core::arch::x86_64::_rdtsc() },
+            ticks: hz * CALIBRATION_PERIOD / 1000,
+        })
+    }
+}
+
+impl super::Timer for Tsc {
+    fn sleep(&self, ms: u64) {
+        let target = unsafe { core::arch::x86_64::_rdtsc() } + ms * self.ticks / CALIBRATION_PERIOD;
+
+        while unsafe { core::arch::x86_64::_rdtsc() } < target {
+            unsafe {
+                core::arch::x86_64::_mm_pause();
+            }
+        }
+    }
+}
+
+impl super::ClockSource for Tsc {
+    fn name(&self) -> &'static str {
+        "TSC"
+    }
+
+    fn nanos(&self) -> u64 {
+        // `base` was read on the BSP, the TSC of this core may be behind it
+        let delta = unsafe { core::arch::x86_64::_rdtsc() }.saturating_sub(self.base);
+        let ns = (u128::from(delta) * u128::from(CALIBRATION_PERIOD * 1_000_000)
+            / u128::from(self.ticks)) as u64;
+        LAST_NS.fetch_max(ns, Ordering::AcqRel).max(ns)
+    }
+}